@@ -1,3 +1,5 @@
+#![allow(clippy::single_match, clippy::useless_vec)]
+
 extern crate kn0sys_lmdb_rs as lmdb;
 
 use lmdb::{EnvBuilder, DbFlags};
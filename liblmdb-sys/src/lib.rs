@@ -169,6 +169,7 @@ extern "C" {
     pub fn mdb_env_get_maxkeysize(env: *mut MDB_env) -> c_int;
     pub fn mdb_txn_begin(env: *mut MDB_env, parent: *mut MDB_txn, flags: c_uint, txn: *mut *mut MDB_txn) -> c_int;
     pub fn mdb_txn_env(txn: *mut MDB_txn) -> *mut MDB_env;
+    pub fn mdb_txn_id(txn: *mut MDB_txn) -> size_t;
     pub fn mdb_txn_commit(txn: *mut MDB_txn) -> c_int;
     pub fn mdb_txn_abort(txn: *mut MDB_txn);
     pub fn mdb_txn_reset(txn: *mut MDB_txn);
@@ -0,0 +1,138 @@
+//! Ready-made comparators for keys and duplicate items
+//!
+//! Every comparator comes in two forms: an `extern "C"` function for
+//! `Database::set_compare`/`set_dupsort`, and a plain Rust function of
+//! the same name in `slice` for `EnvBuilder::compare_with`/`dupsort_with`
+//! and `Database::set_compare_with`/`set_dupsort_with`.
+//!
+//! Comparators of fixed size values order values of unexpected size
+//! after all well-formed ones (by length, then bytes), so the ordering
+//! stays total even if such values slip in.
+
+use libc::c_int;
+
+use ffi::MDB_val;
+
+/// Comparators working on plain byte slices
+pub mod slice {
+    use std::cmp::Ordering;
+
+    macro_rules! fixed_size_cmp {
+        ($(#[$doc:meta])* $name:ident, $ty:ty, $decode:ident) => {
+            $(#[$doc])*
+            pub fn $name(a: &[u8], b: &[u8]) -> Ordering {
+                const SIZE: usize = std::mem::size_of::<$ty>();
+                match (<[u8; SIZE]>::try_from(a), <[u8; SIZE]>::try_from(b)) {
+                    (Ok(a), Ok(b)) => <$ty>::$decode(a).cmp(&<$ty>::$decode(b)),
+                    (Ok(_), Err(_)) => Ordering::Less,
+                    (Err(_), Ok(_)) => Ordering::Greater,
+                    (Err(_), Err(_)) => length_then_bytes(a, b),
+                }
+            }
+        }
+    }
+
+    fixed_size_cmp!(
+        /// Native endian `u32`, same order as `DbIntKey`
+        native_u32, u32, from_ne_bytes);
+    fixed_size_cmp!(
+        /// Native endian `u64`, same order as `DbIntKey`
+        native_u64, u64, from_ne_bytes);
+    fixed_size_cmp!(
+        /// Native endian `i32`
+        native_i32, i32, from_ne_bytes);
+    fixed_size_cmp!(
+        /// Native endian `i64`
+        native_i64, i64, from_ne_bytes);
+    fixed_size_cmp!(
+        /// Big endian two's complement `i32`, negative values first
+        big_endian_i32, i32, from_be_bytes);
+    fixed_size_cmp!(
+        /// Big endian two's complement `i64`, negative values first
+        big_endian_i64, i64, from_be_bytes);
+
+    /// ASCII letters compared regardless of case, so keys differing
+    /// only in case are the same key
+    pub fn ascii_case_insensitive(a: &[u8], b: &[u8]) -> Ordering {
+        let a = a.iter().map(u8::to_ascii_lowercase);
+        let b = b.iter().map(u8::to_ascii_lowercase);
+        a.cmp(b)
+    }
+
+    /// Lexicographic order reversed, i.e. descending
+    pub fn reverse_lexicographic(a: &[u8], b: &[u8]) -> Ordering {
+        b.cmp(a)
+    }
+
+    /// Shorter values first, values of the same length lexicographically
+    pub fn length_then_bytes(a: &[u8], b: &[u8]) -> Ordering {
+        a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+    }
+
+    /// Keys encoded by `encode_tuple`, compared element by element,
+    /// a tuple goes before the longer ones it is a prefix of. Keys which
+    /// can't be decoded go after all tuples.
+    pub fn tuple(a: &[u8], b: &[u8]) -> Ordering {
+        match (super::decode_tuple(a), super::decode_tuple(b)) {
+            (Some(a), Some(b)) => a.cmp(&b),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => a.cmp(b),
+        }
+    }
+}
+
+/// Encodes tuple key compared by `tuple`: every element is prefixed
+/// with its length as big endian `u32`
+pub fn encode_tuple(elements: &[&[u8]]) -> Vec<u8> {
+    let size = elements.iter().map(|e| e.len() + 4).sum();
+    let mut key = Vec::with_capacity(size);
+    for element in elements {
+        key.extend_from_slice(&(element.len() as u32).to_be_bytes());
+        key.extend_from_slice(element);
+    }
+    key
+}
+
+/// Decodes tuple key created by `encode_tuple`
+pub fn decode_tuple(mut key: &[u8]) -> Option<Vec<&[u8]>> {
+    let mut elements = Vec::new();
+    while !key.is_empty() {
+        let (len, rest) = key.split_first_chunk::<4>()?;
+        let len = u32::from_be_bytes(*len) as usize;
+        if rest.len() < len {
+            return None;
+        }
+        let (element, rest) = rest.split_at(len);
+        elements.push(element);
+        key = rest;
+    }
+    Some(elements)
+}
+
+pub(crate) unsafe fn as_slice<'a>(val: *const MDB_val) -> &'a [u8] {
+    let val = &*val;
+    if val.mv_size == 0 {
+        &[]
+    } else {
+        std::slice::from_raw_parts(val.mv_data as *const u8, val.mv_size)
+    }
+}
+
+macro_rules! extern_cmp {
+    ($($name:ident),*) => {
+        $(
+            #[doc = concat!("`extern \"C\"` version of [`slice::", stringify!($name), "`]")]
+            // has to be safe to fit MDB_cmp_func, pointers come from LMDB
+            #[allow(clippy::not_unsafe_ptr_arg_deref)]
+            pub extern "C" fn $name(a: *const MDB_val, b: *const MDB_val) -> c_int {
+                unsafe { slice::$name(as_slice(a), as_slice(b)) as c_int }
+            }
+        )*
+    }
+}
+
+extern_cmp!(native_u32, native_u64, native_i32, native_i64,
+            big_endian_i32, big_endian_i64,
+            ascii_case_insensitive, reverse_lexicographic,
+            length_then_bytes, tuple);
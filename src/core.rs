@@ -41,26 +41,34 @@
 
 #![allow(non_upper_case_globals)]
 
-use libc::{c_int, c_uint, size_t, c_void};
+use libc::{c_char, c_int, c_uint, size_t, c_void};
 use std;
 use std::borrow::ToOwned;
-use std::cell::UnsafeCell;
+use std::cell::{Cell, RefCell};
 use std::cmp::Ordering;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
-use std::ffi::CString;
-use std::path::Path;
+use std::ffi::{CStr, CString};
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::mem;
+use std::ops::{Bound, RangeBounds};
 use std::ptr;
-use std::rc::Rc;
 use std::result::Result;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, OnceLock, RwLock, RwLockReadGuard, TryLockError, Weak};
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering as AtomicOrdering};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use ffi::{self, MDB_val};
 pub use MdbError::{NotFound, KeyExists, Other, StateError, Corrupted, Panic};
-pub use MdbError::{InvalidPath, TxnFull, CursorFull, PageFull, CacheError};
-use crate::traits::{ToMdbValue, FromMdbValue};
-use crate::utils::error_msg;
+pub use MdbError::{InvalidPath, TxnFull, CursorFull, PageFull, CacheError, EnvInUse};
+pub use MdbError::{AlreadyOpen, MapResized, FlagsMismatch, StaleHandle, InvalidWriteFlags};
+pub use MdbError::{PathIsFile, PathIsDir, PermissionDenied, DirMissing, SettingsMismatch};
+pub use MdbError::ComparatorsExhausted;
+use crate::comparators::{as_slice, decode_tuple, encode_tuple};
+use crate::traits::{ToMdbValue, FromTxnValue, TxnMode, ReadOnly, ReadWrite};
+use crate::utils::{error_msg, path_to_cstring};
 
 
 macro_rules! lift_mdb {
@@ -112,6 +120,18 @@ pub enum MdbError {
     InvalidPath,
     StateError(String),
     CacheError,
+    EnvInUse { environments: usize, transactions: usize, cursors: usize },
+    AlreadyOpen,
+    MapResized,
+    FlagsMismatch { requested: DbFlags, actual: DbFlags },
+    StaleHandle,
+    InvalidWriteFlags { flags: WriteFlags, reason: &'static str },
+    PathIsFile(PathBuf),
+    PathIsDir(PathBuf),
+    PermissionDenied(PathBuf),
+    DirMissing(PathBuf),
+    SettingsMismatch { setting: &'static str },
+    ComparatorsExhausted,
     Other(c_int, String)
 }
 
@@ -126,6 +146,7 @@ impl MdbError {
             ffi::MDB_PAGE_FULL   => PageFull,
             ffi::MDB_CORRUPTED   => Corrupted,
             ffi::MDB_PANIC       => Panic,
+            ffi::MDB_MAP_RESIZED => MapResized,
             _                    => Other(code, error_msg(code))
         }
     }
@@ -137,8 +158,23 @@ impl std::fmt::Display for MdbError {
         match self {
             &NotFound | &KeyExists | &TxnFull |
             &CursorFull | &PageFull | &Corrupted |
-            &Panic | &InvalidPath | &CacheError => write!(fmt, "{:?}", self),
+            &Panic | &InvalidPath | &CacheError |
+            &AlreadyOpen | &MapResized => write!(fmt, "{:?}", self),
+            FlagsMismatch { requested, actual } =>
+                write!(fmt, "db flags mismatch: requested {:?}, db has {:?}", requested, actual),
+            StaleHandle => write!(fmt, "db handle was closed or its db was dropped"),
+            InvalidWriteFlags { flags, reason } => write!(fmt, "invalid write flags {:?}: {}", flags, reason),
+            PathIsFile(path) => write!(fmt, "{} is a file, directory expected", path.display()),
+            PathIsDir(path) => write!(fmt, "{} is a directory, data file expected", path.display()),
+            PermissionDenied(path) => write!(fmt, "permission denied: {}", path.display()),
+            DirMissing(path) => write!(fmt, "directory {} doesn't exist", path.display()),
+            SettingsMismatch { setting } => write!(fmt, "environment is already open with different {}", setting),
+            ComparatorsExhausted =>
+                write!(fmt, "all {} comparator slots of the process are in use", MAX_COMPARATORS),
             StateError(msg) => write!(fmt, "{}", msg),
+            &EnvInUse { environments, transactions, cursors } =>
+                write!(fmt, "environment in use: {} other handle(s), {} transaction(s), {} cursor(s)",
+                       environments, transactions, cursors),
             &Other(code, ref msg) => write!(fmt, "{}: {}", code, msg)
         }
     }
@@ -157,6 +193,18 @@ impl Error for MdbError {
             InvalidPath => "invalid path for database",
             StateError(_) => "state error",
             CacheError => "db cache error",
+            EnvInUse { .. } => "environment in use",
+            AlreadyOpen => "environment is already open in this process",
+            MapResized => "map was resized by another process",
+            FlagsMismatch { .. } => "db flags mismatch",
+            StaleHandle => "stale db handle",
+            InvalidWriteFlags { .. } => "invalid write flags",
+            PathIsFile(_) => "path is a file",
+            PathIsDir(_) => "path is a directory",
+            PermissionDenied(_) => "permission denied",
+            DirMissing(_) => "directory doesn't exist",
+            SettingsMismatch { .. } => "environment is already open with different settings",
+            ComparatorsExhausted => "comparator slots exhausted",
             Other(_, _) => "other error",
         }
     }
@@ -339,7 +387,7 @@ bitflags! {
 bitflags! {
     #[doc = "A set of database flags"]
 
-    #[derive(Debug, Copy, Clone)]
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
     pub struct DbFlags: c_uint {
         #[doc="Keys are strings to be compared in reverse order, from the"]
         #[doc=" end of the strings to the beginning. By default, Keys are"]
@@ -375,68 +423,371 @@ bitflags! {
     }
 }
 
-/// Database
+impl DbFlags {
+    /// Flags which are stored in database, i.e. all but `DbCreate`
+    fn persistent(self) -> DbFlags {
+        self - DbFlags::DbCreate
+    }
+}
+
+bitflags! {
+    #[doc = "A set of flags for writing an item"]
+
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub struct WriteFlags: c_uint {
+        #[doc="Fail with KeyExists if the key already has a value,"]
+        #[doc=" even when duplicates are allowed."]
+        const NoOverwrite = ffi::MDB_NOOVERWRITE;
+        #[doc="Fail with KeyExists if the key/item pair already exists."]
+        #[doc=" Only for DbAllowDups databases."]
+        const NoDupData   = ffi::MDB_NODUPDATA;
+        #[doc="Replace the item at the current cursor position, the key"]
+        #[doc=" must be the same. Only for cursors."]
+        const Current     = ffi::MDB_CURRENT;
+        #[doc="Reserve space for the value to be written in place. Only"]
+        #[doc=" through `Database::reserve`, not for DbAllowDups databases."]
+        const Reserve     = ffi::MDB_RESERVE;
+        #[doc="Append the key to the end of the database, fails with"]
+        #[doc=" KeyExists if it isn't greater than all existing keys."]
+        const Append      = ffi::MDB_APPEND;
+        #[doc="Append the item to the end of the key's items. Only for"]
+        #[doc=" DbAllowDups databases."]
+        const AppendDup   = ffi::MDB_APPENDDUP;
+        #[doc="Store several fixed size items at once. Only for DbDupFixed"]
+        #[doc=" databases through `Cursor::set_multiple`."]
+        const Multiple    = ffi::MDB_MULTIPLE;
+    }
+}
+
+impl WriteFlags {
+    /// Checks that flags make sense for a database with `db_flags`,
+    /// `for_cursor` tells whether cursor only flags are allowed
+    fn check(self, db_flags: DbFlags, for_cursor: bool) -> MdbResult<()> {
+        let dups = db_flags.contains(DbFlags::DbAllowDups);
+        let reason = if !for_cursor && self.contains(WriteFlags::Current) {
+            Some("Current can only be used with a cursor")
+        } else if self.contains(WriteFlags::Multiple) {
+            Some("Multiple can only be used with Cursor::set_multiple")
+        } else if self.contains(WriteFlags::Reserve) {
+            Some("Reserve can only be used with Database::reserve")
+        } else if !dups && self.intersects(WriteFlags::NoDupData | WriteFlags::AppendDup) {
+            Some("NoDupData and AppendDup require a DbAllowDups db")
+        } else {
+            None
+        };
+        match reason {
+            Some(reason) => Err(InvalidWriteFlags { flags: self, reason }),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Database, `M` is the mode of the transaction it is bound to
 #[derive(Debug)]
-pub struct Database<'a> {
+pub struct Database<'a, M> {
     handle: ffi::MDB_dbi,
+    generation: usize,
+    // handle was closed before it was bound
+    is_stale: bool,
+    flags: DbFlags,
     txn: &'a NativeTransaction<'a>,
+    marker: ::std::marker::PhantomData<M>,
+}
+
+/// What `Database::read_modify_write` does with the current value
+enum Change {
+    Keep,
+    Put(Vec<u8>),
+    Delete,
 }
 
 // FIXME: provide different interfaces for read-only/read-write databases
 // FIXME: provide different interfaces for simple KV and storage with duplicates
 
-impl<'a> Database<'a> {
-    fn new_with_handle(handle: ffi::MDB_dbi, txn: &'a NativeTransaction<'a>) -> Database<'a> {
-        Database { handle, txn }
+impl<'a, M: TxnMode> Database<'a, M> {
+    /// Binds handle to transaction. Handles can't be closed while any
+    /// transaction is alive, so a handle found valid here stays valid
+    /// unless it was opened by a nested transaction which is aborted.
+    fn new_with_handle(db: &DbHandle, txn: &'a NativeTransaction<'a>) -> Database<'a, M> {
+        let is_stale = txn.env.env.check_db(db.handle, db.generation).is_err();
+        Database { handle: db.handle, generation: db.generation, is_stale, flags: db.flags, txn, marker: ::std::marker::PhantomData }
+    }
+
+    /// Opens a cursor of the same mode as db
+    fn cursor(&self, dbi: ffi::MDB_dbi) -> MdbResult<Cursor<'a, M>> {
+        Cursor::new(self.txn, dbi)
+    }
+
+    /// Returns db handle if it wasn't closed or dropped since it was opened
+    fn dbi(&self) -> MdbResult<ffi::MDB_dbi> {
+        if self.is_stale {
+            return Err(StaleHandle);
+        }
+        self.txn.env.env.check_db(self.handle, self.generation)?;
+        Ok(self.handle)
     }
 
     /// Retrieves current db's statistics.
     pub fn stat(&'a self) -> MdbResult<ffi::MDB_stat> {
-        self.txn.stat(self.handle)
+        self.txn.stat(self.dbi()?)
+    }
+
+    /// Number of items in db, i.e. including every item of a key
+    /// in case of DbAllowDups
+    pub fn len(&self) -> MdbResult<usize> {
+        self.txn.stat(self.dbi()?).map(|stat| stat.ms_entries)
+    }
+
+    /// Checks whether db has no items
+    pub fn is_empty(&self) -> MdbResult<bool> {
+        self.len().map(|len| len == 0)
+    }
+
+    /// Counts items with keys in range. LMDB keeps no counts for key
+    /// ranges, so it walks the range key by key, the cost grows with the
+    /// number of keys in it. Nothing is decoded and duplicates of a key
+    /// are counted at once.
+    pub fn count_range<K: ToMdbValue, R: RangeBounds<K>>(&self, range: R) -> MdbResult<usize> {
+        self.count_in_range(range, true)
+    }
+
+    /// Number of distinct keys, for db without DbAllowDups it is the
+    /// same as `len`
+    pub fn distinct_keys(&self) -> MdbResult<usize> {
+        if self.flags.contains(DbFlags::DbAllowDups) {
+            self.count_in_range::<MdbValue, _>(.., false)
+        } else {
+            self.len()
+        }
+    }
+
+    /// Returns up to `n` distinct random keys, the same ones for the same
+    /// `seed` and data. Keys are found by seeking to random points
+    /// between the first and the last key, so they are close to uniform
+    /// when keys are spread evenly. If keys aren't ordered as plain bytes
+    /// or seeks keep hitting the same keys, every key is scanned with
+    /// reservoir sampling instead, which is uniform.
+    pub fn sample<K: FromTxnValue<'a, M>>(&self, n: usize, seed: u64) -> MdbResult<Vec<K>> {
+        let dbi = self.dbi()?;
+        let mut rng = SplitMix64(seed);
+        let keys = if n == 0 {
+            Vec::new()
+        } else if n >= self.txn.stat(dbi)?.ms_entries {
+            // there is no more keys than wanted
+            self.sample_reservoir(dbi, n, &mut rng)?
+        } else {
+            match self.sample_by_seeks(dbi, n, &mut rng)? {
+                Some(keys) => keys,
+                None => self.sample_reservoir(dbi, n, &mut rng)?,
+            }
+        };
+        Ok(keys.iter().map(|k| FromTxnValue::from_txn_value(k.data())).collect())
+    }
+
+    /// Samples keys by seeking to random keys, `None` if it didn't work out
+    fn sample_by_seeks(&self, dbi: ffi::MDB_dbi, n: usize, rng: &mut SplitMix64) -> MdbResult<Option<Vec<MdbValue<'a>>>> {
+        if self.flags.intersects(DbFlags::DbIntKey | DbFlags::DbReverseKey) {
+            return Ok(None);
+        }
+        let mut cursor = self.cursor(dbi)?;
+        cursor.to_first()?;
+        let lo = cursor.get_plain()?.0.data();
+        cursor.to_last()?;
+        let hi = cursor.get_plain()?.0.data();
+
+        // random keys share the common prefix of lo and hi followed
+        // by 8 bytes between theirs
+        let prefix = lo.iter().zip(hi).take_while(|(a, b)| a == b).count();
+        if prefix + 8 > self.txn.env.get_maxkeysize() as usize {
+            return Ok(None);
+        }
+        let tail = |key: &[u8]| {
+            let mut bytes = [0u8; 8];
+            let rest = &key[prefix.min(key.len())..];
+            let len = rest.len().min(8);
+            bytes[..len].copy_from_slice(&rest[..len]);
+            u64::from_be_bytes(bytes)
+        };
+        let (lo_tail, hi_tail) = (tail(lo), tail(hi));
+        let mut random_key = || {
+            let mut key = hi[..prefix].to_vec();
+            let r = lo_tail + rng.below((hi_tail - lo_tail).wrapping_add(1));
+            key.extend_from_slice(&r.to_be_bytes());
+            key
+        };
+
+        // seeks are only meaningful if the db orders keys as bytes
+        if lo.cmp(hi) == Ordering::Greater {
+            return Ok(None);
+        }
+        for _ in 0..8 {
+            let (a, b) = (random_key(), random_key());
+            if self.txn.cmp_keys(dbi, &a.to_mdb_value(), &b.to_mdb_value()) != a.cmp(&b) {
+                return Ok(None);
+            }
+        }
+
+        let mut seen = HashSet::new();
+        let mut keys = Vec::with_capacity(n);
+        for _ in 0..n * 8 + 64 {
+            let key = random_key();
+            match cursor.to_gte_key(&key) {
+                Ok(()) => (),
+                // random key went past the last one
+                Err(NotFound) => cursor.to_last()?,
+                Err(e) => return Err(e),
+            }
+            let (found, _) = cursor.get_plain()?;
+            if seen.insert(found.data()) {
+                keys.push(found);
+                if keys.len() == n {
+                    return Ok(Some(keys));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Samples keys by reservoir sampling over all keys
+    fn sample_reservoir(&self, dbi: ffi::MDB_dbi, n: usize, rng: &mut SplitMix64) -> MdbResult<Vec<MdbValue<'a>>> {
+        let mut cursor = self.cursor(dbi)?;
+        let mut keys = Vec::with_capacity(n);
+        let mut res = cursor.to_first();
+        let mut seen = 0u64;
+        loop {
+            match res {
+                Ok(()) => (),
+                Err(NotFound) => return Ok(keys),
+                Err(e) => return Err(e),
+            }
+            let (key, _) = cursor.get_plain()?;
+            if keys.len() < n {
+                keys.push(key);
+            } else {
+                let i = rng.below(seen + 1) as usize;
+                if i < n {
+                    keys[i] = key;
+                }
+            }
+            seen += 1;
+            res = cursor.to_next_key();
+        }
+    }
+
+    fn count_in_range<K: ToMdbValue, R: RangeBounds<K>>(&self, range: R, count_items: bool) -> MdbResult<usize> {
+        let dups = self.flags.contains(DbFlags::DbAllowDups);
+        let mut cursor = self.cursor(self.dbi()?)?;
+        let mut res = match range.start_bound() {
+            Bound::Unbounded => cursor.to_first(),
+            Bound::Included(start) => cursor.to_gte_key(start),
+            Bound::Excluded(start) => match cursor.to_gte_key(start) {
+                Ok(()) if cursor.cmp_key(&start.to_mdb_value())? == Ordering::Equal => cursor.to_next_key(),
+                res => res,
+            },
+        };
+
+        let mut count = 0;
+        while res.is_ok() {
+            let in_range = match range.end_bound() {
+                Bound::Unbounded => true,
+                Bound::Included(end) => cursor.cmp_key(&end.to_mdb_value())? != Ordering::Greater,
+                Bound::Excluded(end) => cursor.cmp_key(&end.to_mdb_value())? == Ordering::Less,
+            };
+            if !in_range {
+                break;
+            }
+            count += if dups && count_items { cursor.item_count()? } else { 1 };
+            res = cursor.to_next_key();
+        }
+
+        match res {
+            Ok(()) | Err(NotFound) => Ok(count),
+            Err(e) => Err(e),
+        }
     }
 
     /// Retrieves a value by key. In case of DbAllowDups it will be the first value
-    pub fn get<V: FromMdbValue + 'a>(&'a self, key: &dyn ToMdbValue) -> MdbResult<V> {
-        self.txn.get(self.handle, key)
+    pub fn get<V: FromTxnValue<'a, M>>(&self, key: &dyn ToMdbValue) -> MdbResult<V> {
+        self.txn.get(self.dbi()?, key)
+    }
+
+    /// Checks whether key exists
+    pub fn contains<K: ToMdbValue>(&self, key: &K) -> MdbResult<bool> {
+        self.txn.get_raw(self.dbi()?, key).map(|v| v.is_some())
+    }
+
+    /// Retrieves size of value for key without decoding it, `None` if
+    /// key doesn't exist. In case of DbAllowDups it is the first item.
+    pub fn value_len<K: ToMdbValue>(&self, key: &K) -> MdbResult<Option<usize>> {
+        self.txn.get_raw(self.dbi()?, key).map(|v| v.map(|v| v.mv_size))
+    }
+
+    /// Checks whether key has the item, makes sense mostly with DbAllowDups
+    pub fn contains_item<K: ToMdbValue, V: ToMdbValue>(&self, key: &K, value: &V) -> MdbResult<bool> {
+        let mut cursor = self.cursor(self.dbi()?)?;
+        match cursor.to_item(key, value) {
+            Ok(()) => Ok(true),
+            Err(NotFound) => Ok(false),
+            Err(e) => Err(e),
+        }
     }
 
     /// Sets value for key. In case of DbAllowDups it will add a new item
     pub fn set(&self, key: &dyn ToMdbValue, value: &dyn ToMdbValue) -> MdbResult<()> {
-        self.txn.set(self.handle, key, value)
+        self.txn.set(self.dbi()?, key, value)
     }
 
     /// Appends new key-value pair to database, starting a new page instead of splitting an
     /// existing one if necessary. Requires that key be >= all existing keys in the database
     /// (or will return KeyExists error).
     pub fn append<K: ToMdbValue, V: ToMdbValue>(&self, key: &K, value: &V) -> MdbResult<()> {
-        self.txn.append(self.handle, key, value)
+        self.txn.append(self.dbi()?, key, value)
     }
 
     /// Appends new value for the given key (requires DbAllowDups), starting a new page instead
     /// of splitting an existing one if necessary. Requires that value be >= all existing values
     /// for the given key (or will return KeyExists error).
     pub fn append_duplicate<K: ToMdbValue, V: ToMdbValue>(&self, key: &K, value: &V) -> MdbResult<()> {
-        self.txn.append_duplicate(self.handle, key, value)
+        self.txn.append_duplicate(self.dbi()?, key, value)
+    }
+
+    /// Sets value for key the way `flags` tell, fails with
+    /// `InvalidWriteFlags` if they don't fit this database
+    pub fn put_with_flags<K: ToMdbValue, V: ToMdbValue>(&self, key: &K, value: &V, flags: WriteFlags) -> MdbResult<()> {
+        let dbi = self.dbi()?;
+        flags.check(self.txn.db_flags(dbi)?, false)?;
+        self.txn.put_with_flags(dbi, key, value, flags)
+    }
+
+    /// Reserves `len` bytes for the value of key and lets `fill` write
+    /// them in place, saving a copy of the value. Not for DbAllowDups
+    /// databases.
+    pub fn reserve<K: ToMdbValue, F: FnOnce(&mut [u8])>(&self, key: &K, len: usize, fill: F) -> MdbResult<()> {
+        let dbi = self.dbi()?;
+        if self.txn.db_flags(dbi)?.contains(DbFlags::DbAllowDups) {
+            return Err(InvalidWriteFlags { flags: WriteFlags::Reserve, reason: "Reserve can't be used with a DbAllowDups db" });
+        }
+        self.txn.reserve(dbi, key, len, fill)
     }
 
     /// Set value for key. Fails if key already exists, even when duplicates are allowed.
     pub fn insert(&self, key: &dyn ToMdbValue, value: &dyn ToMdbValue) -> MdbResult<()> {
-        self.txn.insert(self.handle, key, value)
+        self.txn.insert(self.dbi()?, key, value)
     }
 
     /// Deletes value for key.
     pub fn del(&self, key: &dyn ToMdbValue) -> MdbResult<()> {
-        self.txn.del(self.handle, key)
+        self.txn.del(self.dbi()?, key)
     }
 
     /// Should be used only with DbAllowDups. Deletes corresponding (key, value)
     pub fn del_item(&self, key: &dyn ToMdbValue, data: &dyn ToMdbValue) -> MdbResult<()> {
-        self.txn.del_item(self.handle, key, data)
+        self.txn.del_item(self.dbi()?, key, data)
     }
 
     /// Returns a new cursor
-    pub fn new_cursor(&'a self) -> MdbResult<Cursor<'a>> {
-        self.txn.new_cursor(self.handle)
+    pub fn new_cursor(&self) -> MdbResult<Cursor<'a, M>> {
+        self.txn.new_cursor(self.dbi()?)
     }
 
     /// Deletes current db, also moves it out
@@ -446,26 +797,26 @@ impl<'a> Database<'a> {
 
     /// Removes all key/values from db
     pub fn clear(&self) -> MdbResult<()> {
-        self.txn.clear_db(self.handle)
+        self.txn.clear_db(self.dbi()?)
     }
 
     /// Returns an iterator for all values in database
-    pub fn iter(&'a self) -> MdbResult<CursorIterator<'a, CursorIter>> {
-        self.txn.new_cursor(self.handle)
+    pub fn iter(&self) -> MdbResult<CursorIterator<'a, CursorIter, M>> {
+        self.txn.new_cursor(self.dbi()?)
             .map(|c| CursorIterator::wrap(c, CursorIter))
     }
 
     /// Returns an iterator through keys starting with start_key (>=), start_key is included
-    pub fn keyrange_from<'c, K: ToMdbValue + 'c>(&'c self, start_key: &'c K) -> MdbResult<CursorIterator<'c, CursorFromKeyIter>> {
-        let cursor = self.txn.new_cursor(self.handle)?;
+    pub fn keyrange_from<'k, K: ToMdbValue>(&self, start_key: &'k K) -> MdbResult<CursorIterator<'a, CursorFromKeyIter<'k>, M>> {
+        let cursor = self.txn.new_cursor(self.dbi()?)?;
         let key_range = CursorFromKeyIter::new(start_key);
         let wrap = CursorIterator::wrap(cursor, key_range);
         Ok(wrap)
     }
 
     /// Returns an iterator through keys less than end_key, end_key is not included
-    pub fn keyrange_to<'c, K: ToMdbValue + 'c>(&'c self, end_key: &'c K) -> MdbResult<CursorIterator<'c, CursorToKeyIter>> {
-        let cursor = self.txn.new_cursor(self.handle)?;
+    pub fn keyrange_to<'k, K: ToMdbValue>(&self, end_key: &'k K) -> MdbResult<CursorIterator<'a, CursorToKeyIter<'k>, M>> {
+        let cursor = self.txn.new_cursor(self.dbi()?)?;
         let key_range = CursorToKeyIter::new(end_key);
         let wrap = CursorIterator::wrap(cursor, key_range);
         Ok(wrap)
@@ -473,10 +824,10 @@ impl<'a> Database<'a> {
 
     /// Returns an iterator through keys `start_key <= x < end_key`. This is, start_key is
     /// included in the iteration, while end_key is kept excluded.
-    pub fn keyrange_from_to<'c, K: ToMdbValue + 'c>(&'c self, start_key: &'c K, end_key: &'c K)
-                               -> MdbResult<CursorIterator<'c, CursorKeyRangeIter>>
+    pub fn keyrange_from_to<'k, K: ToMdbValue>(&self, start_key: &'k K, end_key: &'k K)
+                               -> MdbResult<CursorIterator<'a, CursorKeyRangeIter<'k>, M>>
     {
-        let cursor = self.txn.new_cursor(self.handle)?;
+        let cursor = self.txn.new_cursor(self.dbi()?)?;
         let key_range = CursorKeyRangeIter::new(start_key, end_key, false);
         let wrap = CursorIterator::wrap(cursor, key_range);
         Ok(wrap)
@@ -486,20 +837,225 @@ impl<'a> Database<'a> {
     /// Currently it works only for unique keys (i.e. it will skip
     /// multiple items when DB created with ffi::MDB_DUPSORT).
     /// Iterator is valid while cursor is valid
-    pub fn keyrange<'c, K: ToMdbValue + 'c>(&'c self, start_key: &'c K, end_key: &'c K)
-                               -> MdbResult<CursorIterator<'c, CursorKeyRangeIter>>
+    pub fn keyrange<'k, K: ToMdbValue>(&self, start_key: &'k K, end_key: &'k K)
+                               -> MdbResult<CursorIterator<'a, CursorKeyRangeIter<'k>, M>>
     {
-        let cursor = self.txn.new_cursor(self.handle)?;
+        let cursor = self.txn.new_cursor(self.dbi()?)?;
         let key_range = CursorKeyRangeIter::new(start_key, end_key, true);
         let wrap = CursorIterator::wrap(cursor, key_range);
         Ok(wrap)
     }
 
     /// Returns an iterator for all items (i.e. values with same key)
-    pub fn item_iter<'c, 'db: 'c, K: ToMdbValue>(&'db self, key: &'c K) -> MdbResult<CursorIterator<'c, CursorItemIter<'c>>> {
-        let cursor = self.txn.new_cursor(self.handle)?;
-        let inner_iter = CursorItemIter::<'c>::new(key);
-        Ok(CursorIterator::<'c>::wrap(cursor, inner_iter))
+    pub fn item_iter<'k, K: ToMdbValue>(&self, key: &'k K) -> MdbResult<CursorIterator<'a, CursorItemIter<'k>, M>> {
+        let cursor = self.txn.new_cursor(self.dbi()?)?;
+        let inner_iter = CursorItemIter::new(key);
+        Ok(CursorIterator::wrap(cursor, inner_iter))
+    }
+
+    /// Returns up to `limit` entries following the position `after`
+    /// points to, or from the start for `None`, and a token to get the
+    /// next page with. Position is a key, plus an item in case of
+    /// DbAllowDups, so pages resume right even if entries were added
+    /// or deleted in between.
+    pub fn page(&self, after: Option<&PageToken>, limit: usize, direction: Direction) -> MdbResult<Page<'a, M>> {
+        if limit == 0 {
+            return Err(StateError("page limit must be positive".to_owned()));
+        }
+        let dups = self.flags.contains(DbFlags::DbAllowDups);
+        let mut cursor = self.cursor(self.dbi()?)?;
+        let mut res = match (after, direction) {
+            (Some(token), _) => token.resume(&mut cursor, direction),
+            (None, Direction::Forward) => cursor.to_first(),
+            (None, Direction::Backward) => cursor.to_last(),
+        };
+        let step = match direction {
+            Direction::Forward => ffi::MDB_cursor_op::MDB_NEXT,
+            Direction::Backward => ffi::MDB_cursor_op::MDB_PREV,
+        };
+
+        let mut entries = Vec::new();
+        loop {
+            match res {
+                Ok(()) => (),
+                Err(NotFound) => return Ok(Page { entries, next: None }),
+                Err(e) => return Err(e),
+            }
+            if entries.len() == limit {
+                break;
+            }
+            let (key, value) = cursor.get_plain()?;
+            entries.push(CursorValue::new(key, value));
+            res = cursor.navigate(step);
+        }
+
+        // there is at least one more entry
+        let last = &entries[entries.len() - 1];
+        let token = if dups {
+            encode_tuple(&[last.key.as_bytes(), last.value.as_bytes()])
+        } else {
+            encode_tuple(&[last.key.as_bytes()])
+        };
+        Ok(Page { entries, next: Some(PageToken(token)) })
+    }
+
+    /// Splits keys into about `n` ranges holding roughly the same number
+    /// of items. Bounds are picked among keys sampled by seeks, see
+    /// `sample`, so ranges are even when keys are spread evenly. Small
+    /// databases, and those seeks don't work for, are split exactly by
+    /// walking keys with a cursor without reading values. There are
+    /// fewer ranges if there are too few keys.
+    pub fn split_ranges(&self, n: usize) -> MdbResult<Vec<KeyRange>> {
+        let dbi = self.dbi()?;
+        let entries = self.txn.stat(dbi)?.ms_entries;
+        let bounds = if n < 2 || entries == 0 {
+            Vec::new()
+        } else if entries <= SPLIT_WALK_MAX || n * SPLIT_SAMPLES >= entries {
+            self.split_by_walk(dbi, n, entries)?
+        } else {
+            match self.split_by_seeks(dbi, n)? {
+                Some(bounds) => bounds,
+                None => self.split_by_walk(dbi, n, entries)?,
+            }
+        };
+
+        let mut ranges = Vec::with_capacity(bounds.len() + 1);
+        let mut start = None;
+        for bound in bounds {
+            ranges.push(KeyRange { start, end: Some(bound.clone()) });
+            start = Some(bound);
+        }
+        ranges.push(KeyRange { start, end: None });
+        Ok(ranges)
+    }
+
+    /// Picks bounds of `split_ranges` among sampled keys, `None` if
+    /// sampling by seeks didn't work out
+    fn split_by_seeks(&self, dbi: ffi::MDB_dbi, n: usize) -> MdbResult<Option<Vec<Vec<u8>>>> {
+        // fixed seed, so that the same data is split the same way
+        let mut rng = SplitMix64(0);
+        let mut keys: Vec<&[u8]> = match self.sample_by_seeks(dbi, n * SPLIT_SAMPLES, &mut rng)? {
+            Some(keys) => keys.iter().map(|k| k.data()).collect(),
+            None => return Ok(None),
+        };
+        // sample_by_seeks made sure the db orders keys as bytes
+        keys.sort_unstable();
+        // every bound has sampled keys before it, so no range is empty
+        let mut bounds: Vec<Vec<u8>> = Vec::with_capacity(n - 1);
+        for i in 1..n {
+            let key = keys[i * keys.len() / n];
+            if bounds.last().map(|b| &b[..]) != Some(key) {
+                bounds.push(key.to_vec());
+            }
+        }
+        Ok(Some(bounds))
+    }
+
+    /// Picks bounds of `split_ranges` by walking every key
+    fn split_by_walk(&self, dbi: ffi::MDB_dbi, n: usize, entries: usize) -> MdbResult<Vec<Vec<u8>>> {
+        let dups = self.flags.contains(DbFlags::DbAllowDups);
+        let mut bounds: Vec<Vec<u8>> = Vec::new();
+        let mut cursor = self.cursor(dbi)?;
+        cursor.to_first()?;
+        // items before the current key
+        let mut pos = 0;
+        for i in 1..n {
+            let target = i * entries / n;
+            loop {
+                let count = if dups { cursor.item_count()? } else { 1 };
+                if pos + count > target {
+                    break;
+                }
+                pos += count;
+                cursor.to_next_key()?;
+            }
+            let (key, _) = cursor.get_plain()?;
+            let key = key.as_bytes();
+            if pos > 0 && bounds.last().map(|b| &b[..]) != Some(key) {
+                bounds.push(key.to_vec());
+            }
+        }
+        Ok(bounds)
+    }
+
+    /// Returns an iterator over distinct keys, each coming with its items,
+    /// makes sense mostly with DbAllowDups
+    pub fn iter_groups(&self) -> MdbResult<GroupIter<'a, M>> {
+        Ok(GroupIter {
+            cursor: self.cursor(self.dbi()?)?,
+            dups: self.flags.contains(DbFlags::DbAllowDups),
+            started: false,
+        })
+    }
+
+    /// Returns an iterator over items of key within `range`, in the order
+    /// of the dupsort comparator. Only for DbAllowDups databases.
+    pub fn item_range<'k, K, V, R>(&self, key: &'k K, range: R) -> MdbResult<CursorIterator<'a, CursorItemRangeIter<'k>, M>>
+        where K: ToMdbValue, V: ToMdbValue, R: RangeBounds<V> {
+        self.item_range_iter(key, range, false)
+    }
+
+    /// Same as `item_range` going from the greatest item down
+    pub fn item_range_rev<'k, K, V, R>(&self, key: &'k K, range: R) -> MdbResult<CursorIterator<'a, CursorItemRangeIter<'k>, M>>
+        where K: ToMdbValue, V: ToMdbValue, R: RangeBounds<V> {
+        self.item_range_iter(key, range, true)
+    }
+
+    fn item_range_iter<'k, K, V, R>(&self, key: &'k K, range: R, reverse: bool) -> MdbResult<CursorIterator<'a, CursorItemRangeIter<'k>, M>>
+        where K: ToMdbValue, V: ToMdbValue, R: RangeBounds<V> {
+        if !self.flags.contains(DbFlags::DbAllowDups) {
+            return Err(StateError("item ranges need a DbAllowDups db".to_owned()));
+        }
+        let owned = |bound: Bound<&V>| bound.map(|v| v.to_mdb_value().as_bytes().to_vec());
+        let inner = CursorItemRangeIter {
+            key: key.to_mdb_value(),
+            start: owned(range.start_bound()),
+            end: owned(range.end_bound()),
+            reverse,
+        };
+        Ok(CursorIterator::wrap(self.cursor(self.dbi()?)?, inner))
+    }
+
+    /// Compares two keys the way this database orders them
+    pub fn cmp_keys(&self, a: &dyn ToMdbValue, b: &dyn ToMdbValue) -> MdbResult<Ordering> {
+        let dbi = self.dbi()?;
+        Ok(self.txn.cmp_keys(dbi, &a.to_mdb_value(), &b.to_mdb_value()))
+    }
+
+    /// Retrieves values of many keys at once, in the same order. Keys are
+    /// sorted so that a single cursor only moves forward, which is much
+    /// faster than a `get` per key for large batches.
+    ///
+    /// In case of DbAllowDups it is the first item of a key.
+    pub fn get_many<K, V>(&self, keys: &[K]) -> MdbResult<Vec<Option<V>>>
+        where K: ToMdbValue, V: FromTxnValue<'a, M> {
+        let dbi = self.dbi()?;
+        let keys: Vec<MdbValue> = keys.iter().map(|k| k.to_mdb_value()).collect();
+        let mut order: Vec<usize> = (0..keys.len()).collect();
+        order.sort_by(|&a, &b| self.txn.cmp_keys(dbi, &keys[a], &keys[b]));
+
+        let mut values: Vec<Option<V>> = keys.iter().map(|_| None).collect();
+        let mut cursor = self.cursor(dbi)?;
+        for i in order {
+            match cursor.to_gte_key(&keys[i]) {
+                Ok(()) => (),
+                // the rest of keys are greater still
+                Err(NotFound) => break,
+                Err(e) => return Err(e),
+            }
+            if cursor.cmp_key(&keys[i])? == Ordering::Equal {
+                let value = unsafe { MdbValue::from_raw(&cursor.data_val) };
+                values[i] = Some(FromTxnValue::from_txn_value(value.data()));
+            }
+        }
+        Ok(values)
+    }
+
+    /// Compares two items of the same key the way this database
+    /// orders them, makes sense only with DbAllowDups
+    pub fn cmp_items(&self, a: &dyn ToMdbValue, b: &dyn ToMdbValue) -> MdbResult<Ordering> {
+        let dbi = self.dbi()?;
+        Ok(self.txn.cmp_items(dbi, &a.to_mdb_value(), &b.to_mdb_value()))
     }
 
     /// Sets the key compare function for this database.
@@ -514,9 +1070,22 @@ impl<'a> Database<'a> {
     ///
     /// Setting lasts for the lifetime of the underlying db handle.
     pub fn set_compare(&self, cmp_fn: extern "C" fn(*const MDB_val, *const MDB_val) -> c_int) -> MdbResult<()> {
-        lift_mdb!(unsafe {
-            ffi::mdb_set_compare(self.txn.handle, self.handle, cmp_fn)
-        })
+        let dbi = self.dbi()?;
+        self.txn.env.env.set_extern_comparator(self.txn.handle, dbi, CompareTarget::Keys, cmp_fn)
+    }
+
+    /// Sets a closure as the key compare function for this database.
+    ///
+    /// Same rules as for `set_compare` apply, prefer `EnvBuilder::compare_with`
+    /// which installs it on opening. Comparator must not panic.
+    ///
+    /// At most 64 closures can be installed at the same time across all
+    /// environments of the process, fails with `ComparatorsExhausted`
+    /// past that. Slot is freed once the db handle is closed.
+    pub fn set_compare_with<F>(&self, cmp: F) -> MdbResult<()>
+        where F: Fn(&[u8], &[u8]) -> Ordering + Send + Sync + 'static {
+        let dbi = self.dbi()?;
+        self.txn.env.env.set_comparator(self.txn.handle, dbi, CompareTarget::Keys, Arc::new(cmp))
     }
 
     /// Sets the value comparison function for values of the same key in this database.
@@ -532,24 +1101,101 @@ impl<'a> Database<'a> {
     /// Only used when DbAllowDups is true.
     /// Setting lasts for the lifetime of the underlying db handle.
     pub fn set_dupsort(&self, cmp_fn: extern "C" fn(*const MDB_val, *const MDB_val) -> c_int) -> MdbResult<()> {
-        lift_mdb!(unsafe {
-            ffi::mdb_set_dupsort(self.txn.handle, self.handle, cmp_fn)
-        })
+        let dbi = self.dbi()?;
+        self.txn.env.env.set_extern_comparator(self.txn.handle, dbi, CompareTarget::Items, cmp_fn)
+    }
+
+    /// Sets a closure as the value comparison function for values of
+    /// the same key, see `set_compare_with` for the limit of installed
+    /// closures
+    pub fn set_dupsort_with<F>(&self, cmp: F) -> MdbResult<()>
+        where F: Fn(&[u8], &[u8]) -> Ordering + Send + Sync + 'static {
+        let dbi = self.dbi()?;
+        self.txn.env.env.set_comparator(self.txn.handle, dbi, CompareTarget::Items, Arc::new(cmp))
+    }
+}
+
+
+/// A slot of the reader lock table, as reported by `mdb_reader_list`
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ReaderInfo {
+    /// Process owning the slot
+    pub pid: c_int,
+    /// Thread owning the slot
+    pub thread: usize,
+    /// Snapshot pinned by the reader, `None` if the slot is reset
+    pub txnid: Option<u64>,
+}
+
+impl ReaderInfo {
+    fn parse(line: &str) -> Option<ReaderInfo> {
+        let mut parts = line.split_whitespace();
+        let pid = parts.next()?.parse().ok()?;
+        let thread = usize::from_str_radix(parts.next()?, 16).ok()?;
+        let txnid = match parts.next()? {
+            "-" => None,
+            id => Some(id.parse().ok()?),
+        };
+        Some(ReaderInfo { pid, thread, txnid })
+    }
+}
+
+/// A reader reported by the watchdog started with
+/// `EnvBuilder::reader_maintenance`
+#[derive(Clone, Debug)]
+pub struct LaggingReader {
+    pub reader: ReaderInfo,
+    /// Number of transactions committed since the pinned snapshot
+    pub lag: u64,
+    /// How long the reader has been observed holding the snapshot
+    pub age: Duration,
+}
+
+type LaggingReaderFn = dyn Fn(&LaggingReader) + Send + Sync;
+
+#[derive(Clone)]
+struct ReaderMaintenanceConfig {
+    interval: Duration,
+    max_reader_age: Duration,
+    callback: Option<Arc<LaggingReaderFn>>,
+}
+
+impl std::fmt::Debug for ReaderMaintenanceConfig {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        fmt.debug_struct("ReaderMaintenanceConfig")
+            .field("interval", &self.interval)
+            .field("max_reader_age", &self.max_reader_age)
+            .field("callback", &self.callback.is_some())
+            .finish()
     }
 }
 
+/// Behavior of `EnvBuilder::open` for environments which are
+/// already open in this process
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ReopenPolicy {
+    /// Returns a clone of the existing `Environment`, fails with
+    /// `SettingsMismatch` if it was opened with different settings
+    /// than those set on the builder
+    Reuse,
+    /// Fails with `AlreadyOpen`
+    Fail,
+}
 
 /// Constructs environment with settigs which couldn't be
 /// changed after opening. By default it tries to create
 /// corresponding dir if it doesn't exist, use `autocreate_dir()`
 /// to override that behavior
-#[derive(Copy, Clone, Debug)]
+#[derive(Clone, Debug)]
 pub struct EnvBuilder {
     flags: EnvCreateFlags,
     max_readers: Option<usize>,
     max_dbs: Option<usize>,
     map_size: Option<u64>,
     autocreate_dir: bool,
+    maintenance: Option<ReaderMaintenanceConfig>,
+    reopen_policy: ReopenPolicy,
+    comparators: HashMap<String, DbComparators>,
 }
 
 impl Default for EnvBuilder {
@@ -566,6 +1212,9 @@ impl EnvBuilder {
             max_dbs: None,
             map_size: None,
             autocreate_dir: true,
+            maintenance: None,
+            reopen_policy: ReopenPolicy::Reuse,
+            comparators: HashMap::new(),
         }
     }
 
@@ -595,13 +1244,80 @@ impl EnvBuilder {
     }
 
     /// Sets whetever `lmdb-rs` should try to autocreate dir with default
-    /// permissions on opening (default is true)
+    /// permissions on opening (default is true). In `NoSubDir` mode it is
+    /// the parent directory of the data file which is created.
     pub fn autocreate_dir(mut self, autocreate_dir: bool)  -> EnvBuilder {
         self.autocreate_dir = autocreate_dir;
         self
     }
 
+    /// Starts a background thread which calls `reader_check` every
+    /// `interval` to clear slots left behind by crashed processes, and
+    /// reports readers which kept an outdated snapshot for longer than
+    /// `max_reader_age` (such readers prevent pages from being reused).
+    ///
+    /// The thread is stopped when the last copy of the environment is
+    /// dropped or on `Environment::stop_reader_maintenance`.
+    pub fn reader_maintenance(mut self, interval: Duration, max_reader_age: Duration) -> EnvBuilder {
+        let callback = self.maintenance.and_then(|m| m.callback);
+        self.maintenance = Some(ReaderMaintenanceConfig { interval, max_reader_age, callback });
+        self
+    }
+
+    /// Sets a callback invoked for every lagging reader found by the
+    /// thread started with `reader_maintenance`. Lagging readers are
+    /// logged with `warn!` regardless of the callback.
+    pub fn on_lagging_reader<F>(mut self, callback: F) -> EnvBuilder
+        where F: Fn(&LaggingReader) + Send + Sync + 'static {
+        let callback: Option<Arc<LaggingReaderFn>> = Some(Arc::new(callback));
+        self.maintenance = match self.maintenance {
+            Some(m) => Some(ReaderMaintenanceConfig { callback, ..m }),
+            None => Some(ReaderMaintenanceConfig {
+                interval: Duration::from_secs(60),
+                max_reader_age: Duration::from_secs(300),
+                callback,
+            }),
+        };
+        self
+    }
+
+    /// Sets what happens when opening an environment which is already
+    /// open in this process (default is `ReopenPolicy::Reuse`)
+    pub fn reopen_policy(mut self, reopen_policy: ReopenPolicy) -> EnvBuilder {
+        self.reopen_policy = reopen_policy;
+        self
+    }
+
+    /// Sets key comparison function for database `db_name` ("" for
+    /// the main one). It is installed whenever the database is opened,
+    /// so it is always in place before the first access.
+    ///
+    /// The same ordering must be used every time the database is used.
+    /// Comparator must not panic, the process is aborted otherwise.
+    ///
+    /// At most 64 closures can be installed at the same time across all
+    /// environments of the process, opening the database fails with
+    /// `ComparatorsExhausted` past that.
+    pub fn compare_with<F>(mut self, db_name: &str, cmp: F) -> EnvBuilder
+        where F: Fn(&[u8], &[u8]) -> Ordering + Send + Sync + 'static {
+        self.comparators.entry(db_name.to_owned()).or_default().keys = Some(Arc::new(cmp));
+        self
+    }
+
+    /// Sets comparison function for items of the same key in database
+    /// `db_name` created with `DbAllowDups`, see `compare_with` for
+    /// the limit of installed closures
+    pub fn dupsort_with<F>(mut self, db_name: &str, cmp: F) -> EnvBuilder
+        where F: Fn(&[u8], &[u8]) -> Ordering + Send + Sync + 'static {
+        self.comparators.entry(db_name.to_owned()).or_default().items = Some(Arc::new(cmp));
+        self
+    }
+
     /// Opens environment in specified path
+    ///
+    /// LMDB doesn't allow opening the same environment twice in one
+    /// process, so if it is already open the existing environment is
+    /// returned or `AlreadyOpen` error is raised, see `reopen_policy`.
     pub fn open<P: AsRef<Path>>(self, path: P, perms: u32) -> MdbResult<Environment> {
         let changeable_flags: EnvCreateFlags = 
             EnvCreateFlags::EnvCreataMapAsync 
@@ -609,6 +1325,23 @@ impl EnvBuilder {
             | EnvCreateFlags::EnvCreateNoSync 
             | EnvCreateFlags::EnvCreateNoMetaSync;
 
+        EnvBuilder::check_path(&path, self.flags, self.autocreate_dir)?;
+        let c_path = path_to_cstring(path.as_ref()).ok_or(MdbError::InvalidPath)?;
+
+        // Registry is kept locked until the new environment is registered
+        // so concurrent opens of the same path are serialized
+        let mut registry = match lookup_env_registry(&path, self.flags)? {
+            Ok(registry) => registry,
+            Err(env) => return match self.reopen_policy {
+                ReopenPolicy::Reuse => {
+                    debug!("reusing already open environment {:?}", path.as_ref());
+                    self.check_reused(&env)?;
+                    Ok(env)
+                },
+                ReopenPolicy::Fail => Err(AlreadyOpen),
+            }
+        };
+
         let env: *mut ffi::MDB_env = ptr::null_mut();
         unsafe {
             let p_env: *mut *mut ffi::MDB_env = &env as *const *mut ffi::MDB_env as *mut *mut ffi::MDB_env;
@@ -630,26 +1363,32 @@ impl EnvBuilder {
             try_mdb!(unsafe { ffi::mdb_env_set_maxdbs(env, max_dbs as u32)});
         }
 
-        if self.autocreate_dir {
-            EnvBuilder::check_path(&path, self.flags)?;
-        }
-
         let is_readonly = self.flags.contains(EnvCreateFlags::EnvCreateReadOnly);
 
         let res = unsafe {
-            // FIXME: revert back once `convert` is stable
-            // let c_path = path.as_os_str().to_cstring().unwrap();
-            let path_str = path.as_ref().to_str().ok_or(MdbError::InvalidPath)?;
-            let c_path = CString::new(path_str).map_err(|_| MdbError::InvalidPath)?;
-
-            ffi::mdb_env_open(env, c_path.as_ref().as_ptr(), self.flags.bits(),
+            ffi::mdb_env_open(env, c_path.as_ptr(), self.flags.bits(),
                               perms as ffi::mdb_mode_t)
         };
 
-        let _ = self;
         match res {
             ffi::MDB_SUCCESS => {
-                Ok(Environment::from_raw(env, is_readonly))
+                let key = EnvKey::new(&path, self.flags);
+                let handle = EnvHandle::new(env, key.clone(), self.max_dbs.unwrap_or(0), self.comparators);
+                let env = Environment::from_raw(handle, is_readonly);
+                if let Some(key) = key {
+                    registry.insert(key, EnvRegistryEntry::new(&env));
+                }
+                // dropping env on error below needs registry
+                drop(registry);
+
+                if let Some(config) = self.maintenance {
+                    env.start_reader_maintenance(config)?;
+                }
+                Ok(env)
+            },
+            libc::EACCES | libc::EPERM => {
+                unsafe { ffi::mdb_env_close(env); }
+                Err(MdbError::PermissionDenied(path.as_ref().to_owned()))
             },
             _ => {
                 unsafe { ffi::mdb_env_close(env); }
@@ -659,107 +1398,771 @@ impl EnvBuilder {
 
     }
 
-    fn check_path<P: AsRef<Path>>(path: P, flags: EnvCreateFlags) -> MdbResult<()> {
-        use std::{fs, io};
-
-        if flags.contains(EnvCreateFlags::EnvCreateNoSubDir) {
-            // FIXME: check parent dir existence/absence
-            warn!("checking for path in NoSubDir mode isn't implemented yet");
-            return Ok(());
+    /// Makes sure settings of the builder match those `env` was opened
+    /// with. Only settings made on the builder are checked, flags always
+    /// are, except ones which can be changed after opening. Map size may
+    /// be smaller than the live one, which could have grown since. As
+    /// comparators can't be compared, only the databases they are set
+    /// for are.
+    fn check_reused(&self, env: &Environment) -> MdbResult<()> {
+        let fixed = |flags: EnvCreateFlags| flags.bits() & !EnvFlags::all().bits();
+        let handle = &env.env;
+        let same_maintenance = |config: &ReaderMaintenanceConfig| match handle.maintenance.lock() {
+            Ok(guard) => guard.as_ref().map(|m| m.timing) == Some((config.interval, config.max_reader_age)),
+            Err(_) => false,
+        };
+        let same_comparators = self.comparators.len() == handle.comparators.len()
+            && self.comparators.iter().all(|(name, cmp)| match handle.comparators.get(name) {
+                Some(live) => cmp.keys.is_some() == live.keys.is_some() && cmp.items.is_some() == live.items.is_some(),
+                None => false,
+            });
+
+        let max_readers = env.get_maxreaders()? as usize;
+        let map_size = env.info()?.me_mapsize as u64;
+        let setting = if fixed(self.flags) != fixed(env.get_all_flags()?) {
+            Some("flags")
+        } else if self.max_dbs.is_some_and(|n| n + CORE_DBS != handle.db_slots.len()) {
+            Some("max_dbs")
+        } else if self.max_readers.is_some_and(|n| n != max_readers) {
+            Some("max_readers")
+        } else if self.map_size.is_some_and(|n| n > map_size) {
+            Some("map_size")
+        } else if !self.comparators.is_empty() && !same_comparators {
+            Some("comparators")
+        } else if self.maintenance.as_ref().is_some_and(|config| !same_maintenance(config)) {
+            Some("reader_maintenance")
+        } else {
+            None
+        };
+        match setting {
+            Some(setting) => Err(SettingsMismatch { setting }),
+            None => Ok(()),
         }
+    }
+
+    /// Makes sure the directory which is going to contain data and lock
+    /// files exists: the path itself or its parent in `NoSubDir` mode,
+    /// in which the path itself must not be a directory
+    fn check_path<P: AsRef<Path>>(path: P, flags: EnvCreateFlags, autocreate_dir: bool) -> MdbResult<()> {
+        use std::io;
+
+        let path = path.as_ref();
+        let no_subdir = flags.contains(EnvCreateFlags::EnvCreateNoSubDir);
+        let dir = if no_subdir {
+            match path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent,
+                _ => Path::new("."),
+            }
+        } else {
+            path
+        };
+
+        let to_mdb_error = |e: io::Error| match e.kind() {
+            io::ErrorKind::PermissionDenied => MdbError::PermissionDenied(dir.to_owned()),
+            io::ErrorKind::NotFound => MdbError::DirMissing(dir.to_owned()),
+            _ => MdbError::InvalidPath,
+        };
 
-        // There should be a directory before open
-        match fs::metadata(&path) {
+        match fs::metadata(dir) {
             Ok(meta) => {
-                if meta.is_dir() {
-                    Ok(())
+                if !meta.is_dir() {
+                    Err(MdbError::PathIsFile(dir.to_owned()))
+                } else if no_subdir && path.is_dir() {
+                    Err(MdbError::PathIsDir(path.to_owned()))
                 } else {
-                    Err(MdbError::InvalidPath)
+                    Ok(())
                 }
             },
             Err(e) => {
-                if e.kind() == io::ErrorKind::NotFound {
-                    fs::create_dir_all(path.as_ref()).map_err(|e| {
+                if e.kind() == io::ErrorKind::NotFound && autocreate_dir {
+                    fs::create_dir_all(dir).map_err(|e| {
                         error!("failed to auto create dir: {}", e);
-                        MdbError::InvalidPath
+                        to_mdb_error(e)
                     })
                 } else {
-                    Err(MdbError::InvalidPath)
+                    Err(to_mdb_error(e))
                 }
             }
         }
     }
 }
 
-#[derive(Debug)]
-struct EnvHandle(*mut ffi::MDB_env);
+/// Identity of an environment on disk: canonical path of
+/// the directory, or data file itself in `NoSubDir` mode
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum EnvKey {
+    Path(PathBuf),
+    #[cfg(unix)]
+    Inode { dev: u64, ino: u64 },
+}
 
-impl Drop for EnvHandle {
-    fn drop(&mut self) {
-        unsafe {
-            if !self.0.is_null() {
-                ffi::mdb_env_close(self.0);
+impl EnvKey {
+    /// Returns `None` if there is nothing on disk yet
+    fn new<P: AsRef<Path>>(path: P, flags: EnvCreateFlags) -> Option<EnvKey> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+
+            if flags.contains(EnvCreateFlags::EnvCreateNoSubDir) {
+                return fs::metadata(&path).ok()
+                    .map(|meta| EnvKey::Inode { dev: meta.dev(), ino: meta.ino() });
             }
         }
+        #[cfg(not(unix))]
+        let _ = flags;
+
+        fs::canonicalize(&path).ok().map(EnvKey::Path)
     }
 }
 
-/// Represents LMDB Environment. Should be opened using `EnvBuilder`
 #[derive(Debug)]
-pub struct Environment {
-    env: Rc<EnvHandle>,
-    db_cache: Arc<Mutex<UnsafeCell<HashMap<String, ffi::MDB_dbi>>>>,
-    is_readonly: bool, // true if opened in 'read-only' mode
+struct EnvRegistryEntry {
+    env: Weak<EnvHandle>,
+    db_cache: Weak<Mutex<DbCache>>,
+    is_readonly: bool,
 }
 
-impl Environment {
-    pub fn new() -> EnvBuilder {
-        EnvBuilder::new()
+impl EnvRegistryEntry {
+    fn new(env: &Environment) -> EnvRegistryEntry {
+        EnvRegistryEntry {
+            env: Arc::downgrade(&env.env),
+            db_cache: Arc::downgrade(&env.db_cache),
+            is_readonly: env.is_readonly,
+        }
     }
 
-    fn from_raw(env: *mut ffi::MDB_env, is_readonly: bool) -> Environment {
-        Environment {
-            env: Rc::new(EnvHandle(env)),
-            db_cache: Arc::new(Mutex::new(UnsafeCell::new(HashMap::new()))),
-            is_readonly,
+    fn upgrade(&self) -> Option<Environment> {
+        Some(Environment {
+            env: self.env.upgrade()?,
+            db_cache: self.db_cache.upgrade()?,
+            is_readonly: self.is_readonly,
+        })
+    }
+}
+
+type EnvRegistry = HashMap<EnvKey, EnvRegistryEntry>;
+
+/// Environments open in this process
+static ENV_REGISTRY: OnceLock<Mutex<EnvRegistry>> = OnceLock::new();
+
+fn lock_env_registry() -> MdbResult<MutexGuard<'static, EnvRegistry>> {
+    ENV_REGISTRY.get_or_init(|| Mutex::new(HashMap::new()))
+        .lock()
+        .map_err(|_| MdbError::StateError("environment registry lock poisoned".to_owned()))
+}
+
+/// Returns either locked registry without environment at `path` or
+/// environment registered for it. The key is looked up on disk only
+/// once the registry is locked, as environment may be created by an
+/// open in progress until then.
+fn lookup_env_registry<P: AsRef<Path>>(path: P, flags: EnvCreateFlags)
+        -> MdbResult<Result<MutexGuard<'static, EnvRegistry>, Environment>> {
+    loop {
+        let registry = lock_env_registry()?;
+        let entry = EnvKey::new(&path, flags).and_then(|key| registry.get(&key));
+        match entry {
+            None => return Ok(Ok(registry)),
+            Some(entry) => match entry.upgrade() {
+                Some(env) => return Ok(Err(env)),
+                // the last handle is being dropped right now, it must
+                // finish closing before the path can be opened again
+                None => {
+                    drop(registry);
+                    thread::yield_now();
+                }
+            }
         }
     }
+}
 
-    /// Check for stale entries in the reader lock table.
-    ///
-    /// Returns the number of stale slots that were cleared.
-    pub fn reader_check(&self) -> MdbResult<c_int> {
-        let mut dead: c_int = 0;
-        lift_mdb!(unsafe { ffi::mdb_reader_check(self.env.0, &mut dead as *mut c_int)}, dead)
+#[derive(Debug)]
+struct EnvHandle {
+    ptr: *mut ffi::MDB_env,
+    maintenance: Mutex<Option<ReaderMaintenance>>,
+    live_txns: AtomicUsize,
+    live_cursors: AtomicUsize,
+    registry_key: Option<EnvKey>,
+    // held for read by every transaction from before it begins, for
+    // write while map is resized
+    txn_lock: RwLock<()>,
+    map_resizes: AtomicUsize,
+    db_slots: Vec<DbSlot>,
+    comparators: HashMap<String, DbComparators>,
+    // comparators installed on db handles
+    compare_slots: Mutex<HashMap<(ffi::MDB_dbi, CompareTarget), Comparator>>,
+    // every closure global slots were pointed to, kept until drop as
+    // a comparison might still run when its slot is released, boxed
+    // so they don't move
+    #[allow(clippy::vec_box)]
+    slot_comparators: Mutex<Vec<Box<SlotComparator>>>,
+}
+
+// MDB_env itself is thread safe, the rest is synchronized
+unsafe impl Send for EnvHandle {}
+unsafe impl Sync for EnvHandle {}
+
+impl EnvHandle {
+    fn new(ptr: *mut ffi::MDB_env, registry_key: Option<EnvKey>, max_dbs: usize,
+           comparators: HashMap<String, DbComparators>) -> EnvHandle {
+        EnvHandle {
+            ptr,
+            maintenance: Mutex::new(None),
+            live_txns: AtomicUsize::new(0),
+            live_cursors: AtomicUsize::new(0),
+            registry_key,
+            txn_lock: RwLock::new(()),
+            map_resizes: AtomicUsize::new(0),
+            db_slots: (0..max_dbs + CORE_DBS)
+                .map(|dbi| DbSlot::new(dbi < CORE_DBS))
+                .collect(),
+            comparators,
+            compare_slots: Mutex::new(HashMap::new()),
+            slot_comparators: Mutex::new(Vec::new()),
+        }
     }
 
-    /// Retrieve environment statistics
-    pub fn stat(&self) -> MdbResult<ffi::MDB_stat> {
-        let mut tmp: ffi::MDB_stat = unsafe { std::mem::zeroed() };
-        lift_mdb!(unsafe { ffi::mdb_env_stat(self.env.0, &mut tmp)}, tmp)
+    /// Keeps map from being resized until dropped, taken before a
+    /// transaction begins and held until it is dropped
+    fn txn_guard(&self) -> MdbResult<RwLockReadGuard<'_, ()>> {
+        self.txn_lock.read()
+            .map_err(|_| MdbError::StateError("transaction lock poisoned".to_owned()))
     }
 
-    pub fn info(&self) -> MdbResult<ffi::MDB_envinfo> {
-        let mut tmp: ffi::MDB_envinfo = unsafe { std::mem::zeroed() };
-        lift_mdb!(unsafe { ffi::mdb_env_info(self.env.0, &mut tmp)}, tmp)
+    /// Installs comparators set on builder for `db_name`, must be
+    /// called in the transaction which opened the handle
+    fn set_db_comparators(&self, txn: *mut ffi::MDB_txn, db_name: &str, dbi: ffi::MDB_dbi) -> MdbResult<()> {
+        if let Some(cmps) = self.comparators.get(db_name) {
+            if let Some(ref cmp) = cmps.keys {
+                self.set_comparator(txn, dbi, CompareTarget::Keys, cmp.clone())?;
+            }
+            if let Some(ref cmp) = cmps.items {
+                self.set_comparator(txn, dbi, CompareTarget::Items, cmp.clone())?;
+            }
+        }
+        Ok(())
     }
 
-    /// Sync environment to disk
-    pub fn sync(&self, force: bool) -> MdbResult<()> {
-        lift_mdb!(unsafe { ffi::mdb_env_sync(self.env.0, if force {1} else {0})})
+    fn set_comparator(&self, txn: *mut ffi::MDB_txn, dbi: ffi::MDB_dbi, target: CompareTarget, cmp: Arc<CompareFn>) -> MdbResult<()> {
+        let mut slots = self.lock_compare_slots()?;
+        let mut slot_comparators = self.slot_comparators.lock()
+            .map_err(|_| MdbError::StateError("comparators lock poisoned".to_owned()))?;
+        let boxed = Box::new(SlotComparator(cmp.clone()));
+        let slot = register_comparator(&boxed)?;
+        slot_comparators.push(boxed);
+
+        set_raw_comparator(txn, dbi, target, COMPARE_TRAMPOLINES[slot])
+            .inspect_err(|_| release_comparator(slot))?;
+        // previous one is released only once it isn't installed
+        if let Some(Comparator::Closure(old, _)) = slots.insert((dbi, target), Comparator::Closure(slot, cmp)) {
+            release_comparator(old);
+        }
+        Ok(())
     }
 
-    /// Sets map size.
-    /// This can be called after [open](struct.EnvBuilder.html#method.open) if no transactions are active in this process.
-    pub fn set_mapsize(&self, map_size: usize) -> MdbResult<()> {
-        lift_mdb!(unsafe { ffi::mdb_env_set_mapsize(self.env.0, map_size as size_t)})
+    fn set_extern_comparator(&self, txn: *mut ffi::MDB_txn, dbi: ffi::MDB_dbi, target: CompareTarget, cmp: ffi::MDB_cmp_func) -> MdbResult<()> {
+        let mut slots = self.lock_compare_slots()?;
+        set_raw_comparator(txn, dbi, target, cmp)?;
+        if let Some(Comparator::Closure(slot, _)) = slots.insert((dbi, target), Comparator::Extern(cmp)) {
+            release_comparator(slot);
+        }
+        Ok(())
     }
 
-    /// This one sets only flags which are available for change even
-    /// after opening, see also [get_flags](#method.get_flags) and [get_all_flags](#method.get_all_flags)
+    /// Comparator installed on db handle, `None` if it compares bytes
+    fn comparator(&self, dbi: ffi::MDB_dbi, target: CompareTarget) -> MdbResult<Option<Comparator>> {
+        Ok(self.lock_compare_slots()?.get(&(dbi, target)).cloned())
+    }
+
+    /// Installs comparators of `src` handle of `src_env` on `dst`
+    /// handle, which was opened by `txn`
+    ///
+    /// Fails if `dst` already has different ones, items of `src`
+    /// wouldn't be in order for it.
+    fn carry_comparators(&self, txn: *mut ffi::MDB_txn, dst: ffi::MDB_dbi, src_env: &EnvHandle, src: ffi::MDB_dbi) -> MdbResult<()> {
+        for target in [CompareTarget::Keys, CompareTarget::Items] {
+            match (src_env.comparator(src, target)?, self.comparator(dst, target)?) {
+                (None, None) => (),
+                (Some(ref cmp), Some(ref dst_cmp)) if cmp.is_same(dst_cmp) => (),
+                (Some(Comparator::Closure(_, cmp)), None) => self.set_comparator(txn, dst, target, cmp)?,
+                (Some(Comparator::Extern(cmp)), None) => self.set_extern_comparator(txn, dst, target, cmp)?,
+                _ => return Err(MdbError::StateError(
+                    format!("{:?} comparator of destination db differs from source one", target))),
+            }
+        }
+        Ok(())
+    }
+
+    fn lock_compare_slots(&self) -> MdbResult<MutexGuard<'_, HashMap<(ffi::MDB_dbi, CompareTarget), Comparator>>> {
+        self.compare_slots.lock()
+            .map_err(|_| MdbError::StateError("comparators lock poisoned".to_owned()))
+    }
+
+    /// Frees comparator slots of closed db handle
+    fn release_comparators(&self, dbi: ffi::MDB_dbi) {
+        if let Ok(mut slots) = self.compare_slots.lock() {
+            slots.retain(|&(slot_dbi, _), cmp| {
+                if let (true, &mut Comparator::Closure(slot, _)) = (slot_dbi == dbi, cmp) {
+                    release_comparator(slot);
+                }
+                slot_dbi != dbi
+            });
+        }
+    }
+
+    fn db_generation(&self, dbi: ffi::MDB_dbi) -> usize {
+        self.db_slots.get(dbi as usize)
+            .map_or(0, |slot| slot.generation.load(AtomicOrdering::SeqCst))
+    }
+
+    fn check_db(&self, dbi: ffi::MDB_dbi, generation: usize) -> MdbResult<()> {
+        if self.db_generation(dbi) == generation {
+            Ok(())
+        } else {
+            Err(StaleHandle)
+        }
+    }
+
+    fn db_handle(&self, db: CachedDb) -> DbHandle {
+        DbHandle { handle: db.handle, flags: db.flags, generation: self.db_generation(db.handle) }
+    }
+
+    fn is_db_open(&self, dbi: ffi::MDB_dbi) -> bool {
+        self.db_slots.get(dbi as usize)
+            .is_some_and(|slot| slot.is_open.load(AtomicOrdering::SeqCst))
+    }
+
+    /// Marks slot as open by environment, i.e. it survives
+    /// transaction which opened it
+    fn db_opened(&self, dbi: ffi::MDB_dbi) {
+        if let Some(slot) = self.db_slots.get(dbi as usize) {
+            slot.is_open.store(true, AtomicOrdering::SeqCst);
+        }
+    }
+
+    /// Marks slot as closed, all handles to it become stale
+    fn db_closed(&self, dbi: ffi::MDB_dbi) {
+        if (dbi as usize) < CORE_DBS {
+            return;
+        }
+        if let Some(slot) = self.db_slots.get(dbi as usize) {
+            slot.is_open.store(false, AtomicOrdering::SeqCst);
+            slot.generation.fetch_add(1, AtomicOrdering::SeqCst);
+        }
+        self.release_comparators(dbi);
+    }
+}
+
+/// Number of dbi slots reserved by LMDB, free pages db and main db
+const CORE_DBS: usize = 2;
+
+/// State of a dbi slot. LMDB reuses slots of closed databases, so
+/// generation is bumped on close to tell old handles from new ones
+#[derive(Debug)]
+struct DbSlot {
+    generation: AtomicUsize,
+    is_open: AtomicBool,
+}
+
+impl DbSlot {
+    fn new(is_open: bool) -> DbSlot {
+        DbSlot { generation: AtomicUsize::new(0), is_open: AtomicBool::new(is_open) }
+    }
+}
+
+type CompareFn = dyn Fn(&[u8], &[u8]) -> Ordering + Send + Sync;
+
+/// Comparators set on builder for a database
+#[derive(Clone, Default)]
+struct DbComparators {
+    keys: Option<Arc<CompareFn>>,
+    items: Option<Arc<CompareFn>>,
+}
+
+impl std::fmt::Debug for DbComparators {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        fmt.debug_struct("DbComparators")
+            .field("keys", &self.keys.is_some())
+            .field("items", &self.items.is_some())
+            .finish()
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+enum CompareTarget {
+    Keys,
+    Items,
+}
+
+/// Comparator installed on a db handle
+#[derive(Clone)]
+enum Comparator {
+    /// Closure along with its global slot
+    Closure(usize, Arc<CompareFn>),
+    Extern(ffi::MDB_cmp_func),
+}
+
+impl Comparator {
+    fn is_same(&self, other: &Comparator) -> bool {
+        match (self, other) {
+            (Comparator::Closure(_, a), Comparator::Closure(_, b)) => Arc::ptr_eq(a, b),
+            (&Comparator::Extern(a), &Comparator::Extern(b)) => a as usize == b as usize,
+            _ => false,
+        }
+    }
+}
+
+impl std::fmt::Debug for Comparator {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            Comparator::Closure(slot, _) => write!(fmt, "Closure({})", slot),
+            Comparator::Extern(cmp) => write!(fmt, "Extern({:p})", cmp as *const ()),
+        }
+    }
+}
+
+fn set_raw_comparator(txn: *mut ffi::MDB_txn, dbi: ffi::MDB_dbi, target: CompareTarget, cmp: ffi::MDB_cmp_func) -> MdbResult<()> {
+    lift_mdb!(unsafe {
+        match target {
+            CompareTarget::Keys => ffi::mdb_set_compare(txn, dbi, cmp),
+            CompareTarget::Items => ffi::mdb_set_dupsort(txn, dbi, cmp),
+        }
+    })
+}
+
+/// Max number of closures installed as comparators at the same time
+/// across all environments of the process
+const MAX_COMPARATORS: usize = 64;
+
+/// Closure a global comparator slot points to
+struct SlotComparator(Arc<CompareFn>);
+
+impl std::fmt::Debug for SlotComparator {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        fmt.write_str("SlotComparator")
+    }
+}
+
+// LMDB comparators get no context, so every closure gets a slot with
+// its own trampoline which knows where to look it up. Closures are
+// owned by environments which installed them.
+static COMPARATORS: [AtomicPtr<SlotComparator>; MAX_COMPARATORS] =
+    [const { AtomicPtr::new(ptr::null_mut()) }; MAX_COMPARATORS];
+
+macro_rules! compare_trampolines {
+    ($($slot:literal)*) => {
+        [$(compare_trampoline::<$slot> as ffi::MDB_cmp_func),*]
+    }
+}
+
+static COMPARE_TRAMPOLINES: [ffi::MDB_cmp_func; MAX_COMPARATORS] = compare_trampolines!(
+    0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15
+    16 17 18 19 20 21 22 23 24 25 26 27 28 29 30 31
+    32 33 34 35 36 37 38 39 40 41 42 43 44 45 46 47
+    48 49 50 51 52 53 54 55 56 57 58 59 60 61 62 63
+);
+
+extern "C" fn compare_trampoline<const SLOT: usize>(a: *const MDB_val, b: *const MDB_val) -> c_int {
+    let cmp = COMPARATORS[SLOT].load(AtomicOrdering::Acquire);
+    if cmp.is_null() {
+        // db handle was closed while in use, any order made up here
+        // could corrupt the db
+        error!("comparator slot {} used after its db handle was closed", SLOT);
+        std::process::abort();
+    }
+    let (a, b) = unsafe { (as_slice(a), as_slice(b)) };
+    // environment keeps the closure alive after its slot is released
+    let cmp = unsafe { &(*cmp).0 };
+    cmp(a, b) as c_int
+}
+
+fn register_comparator(cmp: &SlotComparator) -> MdbResult<usize> {
+    let cmp = cmp as *const SlotComparator as *mut SlotComparator;
+    COMPARATORS.iter()
+        .position(|entry| entry.compare_exchange(ptr::null_mut(), cmp, AtomicOrdering::AcqRel, AtomicOrdering::Relaxed).is_ok())
+        .ok_or(ComparatorsExhausted)
+}
+
+fn release_comparator(slot: usize) {
+    COMPARATORS[slot].store(ptr::null_mut(), AtomicOrdering::Release);
+}
+
+impl Drop for EnvHandle {
+    fn drop(&mut self) {
+        // maintenance thread uses raw env, it has to be joined first
+        if let Ok(mut maintenance) = self.maintenance.lock() {
+            maintenance.take();
+        }
+
+        // Closing releases file locks of the whole process, so it must not
+        // overlap with opening the same path again
+        let registry = match self.registry_key {
+            Some(ref key) => match lock_env_registry() {
+                Ok(mut registry) => {
+                    // there is never more than one entry per key, so the
+                    // dead one is this one
+                    if registry.get(key).is_some_and(|e| e.env.strong_count() == 0) {
+                        registry.remove(key);
+                    }
+                    Some(registry)
+                },
+                Err(_) => None,
+            },
+            None => None,
+        };
+
+        unsafe {
+            if !self.ptr.is_null() {
+                ffi::mdb_env_close(self.ptr);
+            }
+        }
+        drop(registry);
+
+        if let Ok(slots) = self.compare_slots.get_mut() {
+            for (_, cmp) in slots.drain() {
+                if let Comparator::Closure(slot, _) = cmp {
+                    release_comparator(slot);
+                }
+            }
+        }
+    }
+}
+
+extern "C" fn collect_reader(msg: *const c_char, ctx: *const c_void) -> c_int {
+    let readers = unsafe { &mut *(ctx as *mut Vec<ReaderInfo>) };
+    let line = unsafe { CStr::from_ptr(msg) }.to_string_lossy();
+    // header and "(no active readers)" lines are simply skipped
+    if let Some(reader) = ReaderInfo::parse(&line) {
+        readers.push(reader);
+    }
+    0
+}
+
+fn env_reader_list(env: *mut ffi::MDB_env) -> MdbResult<Vec<ReaderInfo>> {
+    let mut readers: Vec<ReaderInfo> = Vec::new();
+    let res = unsafe {
+        ffi::mdb_reader_list(env, collect_reader, &mut readers as *mut Vec<ReaderInfo> as *const c_void)
+    };
+    if res < 0 {
+        Err(MdbError::new_with_code(res))
+    } else {
+        Ok(readers)
+    }
+}
+
+fn env_reader_check(env: *mut ffi::MDB_env) -> MdbResult<c_int> {
+    let mut dead: c_int = 0;
+    lift_mdb!(unsafe { ffi::mdb_reader_check(env, &mut dead as *mut c_int)}, dead)
+}
+
+fn env_info(env: *mut ffi::MDB_env) -> MdbResult<ffi::MDB_envinfo> {
+    let mut tmp: ffi::MDB_envinfo = unsafe { std::mem::zeroed() };
+    lift_mdb!(unsafe { ffi::mdb_env_info(env, &mut tmp)}, tmp)
+}
+
+struct EnvPtr(*mut ffi::MDB_env);
+
+// Only used by maintenance thread, which is always joined before
+// environment is closed
+unsafe impl Send for EnvPtr {}
+
+/// Background thread started by `EnvBuilder::reader_maintenance`,
+/// stopped and joined on drop
+#[derive(Debug)]
+struct ReaderMaintenance {
+    stop: Arc<(Mutex<bool>, Condvar)>,
+    thread: Option<thread::JoinHandle<()>>,
+    // interval and max reader age it was started with
+    timing: (Duration, Duration),
+}
+
+impl ReaderMaintenance {
+    fn start(env: *mut ffi::MDB_env, config: ReaderMaintenanceConfig) -> MdbResult<ReaderMaintenance> {
+        let stop = Arc::new((Mutex::new(false), Condvar::new()));
+        let thread_stop = stop.clone();
+        let env = EnvPtr(env);
+        let timing = (config.interval, config.max_reader_age);
+
+        let thread = thread::Builder::new()
+            .name("lmdb-reader-maintenance".to_owned())
+            .spawn(move || {
+                let env = env;
+                let mut seen: HashMap<ReaderInfo, (Instant, bool)> = HashMap::new();
+                while !ReaderMaintenance::wait(&thread_stop, config.interval) {
+                    ReaderMaintenance::run_once(env.0, &config, &mut seen);
+                }
+                debug!("reader maintenance stopped");
+            })
+            .map_err(|e| MdbError::StateError(format!("failed to start reader maintenance: {}", e)))?;
+
+        Ok(ReaderMaintenance { stop, thread: Some(thread), timing })
+    }
+
+    /// Sleeps for interval, returns true if thread was asked to stop
+    fn wait(stop: &(Mutex<bool>, Condvar), interval: Duration) -> bool {
+        let (lock, cvar) = stop;
+        let deadline = Instant::now() + interval;
+        let mut stopped = match lock.lock() {
+            Ok(guard) => guard,
+            Err(_) => return true,
+        };
+        while !*stopped {
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            stopped = match cvar.wait_timeout(stopped, deadline - now) {
+                Ok((guard, _)) => guard,
+                Err(_) => return true,
+            };
+        }
+        true
+    }
+
+    fn run_once(env: *mut ffi::MDB_env,
+                config: &ReaderMaintenanceConfig,
+                seen: &mut HashMap<ReaderInfo, (Instant, bool)>) {
+        match env_reader_check(env) {
+            Ok(0) => (),
+            Ok(dead) => info!("cleared {} stale reader slot(s)", dead),
+            Err(e) => error!("reader check failed: {}", e),
+        }
+
+        let (readers, last_txnid) = match (env_reader_list(env), env_info(env)) {
+            (Ok(readers), Ok(info)) => (readers, info.me_last_txnid as u64),
+            (Err(e), _) | (_, Err(e)) => {
+                error!("failed to list readers: {}", e);
+                return;
+            }
+        };
+
+        let now = Instant::now();
+        seen.retain(|reader, _| readers.contains(reader));
+        for reader in readers.into_iter() {
+            let txnid = match reader.txnid {
+                Some(txnid) => txnid,
+                None => continue,
+            };
+            let entry = seen.entry(reader.clone()).or_insert((now, false));
+            let age = now - entry.0;
+            let lag = last_txnid.saturating_sub(txnid);
+            if entry.1 || lag == 0 || age < config.max_reader_age {
+                continue;
+            }
+            // each snapshot is reported only once
+            entry.1 = true;
+
+            warn!("reader pid={} thread={:x} holds txn {} ({} behind) for {:?}",
+                  reader.pid, reader.thread, txnid, lag, age);
+            if let Some(ref callback) = config.callback {
+                callback(&LaggingReader { reader, lag, age });
+            }
+        }
+    }
+}
+
+impl Drop for ReaderMaintenance {
+    fn drop(&mut self) {
+        let (lock, cvar) = &*self.stop;
+        if let Ok(mut stopped) = lock.lock() {
+            *stopped = true;
+            cvar.notify_all();
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// How many times starting a transaction is retried after
+/// adopting map size grown by another process
+const MAX_MAP_RESIZE_ATTEMPTS: usize = 3;
+
+/// Represents LMDB Environment. Should be opened using `EnvBuilder`
+///
+/// It is cheap to clone and clones could be freely sent to other
+/// threads, underlying environment is closed when the last clone
+/// is dropped or explicitly by `close`.
+#[derive(Debug)]
+pub struct Environment {
+    env: Arc<EnvHandle>,
+    db_cache: Arc<Mutex<DbCache>>,
+    is_readonly: bool, // true if opened in 'read-only' mode
+}
+
+impl Environment {
+    pub fn new() -> EnvBuilder {
+        EnvBuilder::new()
+    }
+
+    fn from_raw(env: EnvHandle, is_readonly: bool) -> Environment {
+        Environment {
+            env: Arc::new(env),
+            db_cache: Arc::new(Mutex::new(HashMap::new())),
+            is_readonly,
+        }
+    }
+
+    /// Check for stale entries in the reader lock table.
+    ///
+    /// Returns the number of stale slots that were cleared.
+    pub fn reader_check(&self) -> MdbResult<c_int> {
+        env_reader_check(self.env.ptr)
+    }
+
+    /// Lists active slots of the reader lock table, including
+    /// readers from other processes
+    pub fn reader_list(&self) -> MdbResult<Vec<ReaderInfo>> {
+        env_reader_list(self.env.ptr)
+    }
+
+    fn start_reader_maintenance(&self, config: ReaderMaintenanceConfig) -> MdbResult<()> {
+        let maintenance = ReaderMaintenance::start(self.env.ptr, config)?;
+        match self.env.maintenance.lock() {
+            Err(_) => Err(MdbError::StateError("reader maintenance lock poisoned".to_owned())),
+            Ok(mut guard) => {
+                *guard = Some(maintenance);
+                Ok(())
+            }
+        }
+    }
+
+    /// Stops the thread started by `EnvBuilder::reader_maintenance`,
+    /// waiting for it to finish. Does nothing if it wasn't started.
+    pub fn stop_reader_maintenance(&self) {
+        // take it out first so the thread isn't joined under the lock
+        let maintenance = match self.env.maintenance.lock() {
+            Ok(mut guard) => guard.take(),
+            Err(_) => None,
+        };
+        drop(maintenance);
+    }
+
+    /// Retrieve environment statistics
+    pub fn stat(&self) -> MdbResult<ffi::MDB_stat> {
+        let mut tmp: ffi::MDB_stat = unsafe { std::mem::zeroed() };
+        lift_mdb!(unsafe { ffi::mdb_env_stat(self.env.ptr, &mut tmp)}, tmp)
+    }
+
+    pub fn info(&self) -> MdbResult<ffi::MDB_envinfo> {
+        env_info(self.env.ptr)
+    }
+
+    /// Sync environment to disk
+    pub fn sync(&self, force: bool) -> MdbResult<()> {
+        lift_mdb!(unsafe { ffi::mdb_env_sync(self.env.ptr, if force {1} else {0})})
+    }
+
+    /// Sets map size.
+    /// This can be called after [open](struct.EnvBuilder.html#method.open) if no transactions are active in this process.
+    pub fn set_mapsize(&self, map_size: usize) -> MdbResult<()> {
+        lift_mdb!(unsafe { ffi::mdb_env_set_mapsize(self.env.ptr, map_size as size_t)})
+    }
+
+    /// This one sets only flags which are available for change even
+    /// after opening, see also [get_flags](#method.get_flags) and [get_all_flags](#method.get_all_flags)
     pub fn set_flags(&mut self, flags: EnvFlags, turn_on: bool) -> MdbResult<()> {
         lift_mdb!(unsafe {
-            ffi::mdb_env_set_flags(self.env.0, flags.bits(), if turn_on {1} else {0})
+            ffi::mdb_env_set_flags(self.env.ptr, flags.bits(), if turn_on {1} else {0})
         })
     }
 
@@ -774,41 +2177,38 @@ impl Environment {
     /// See also [get_flags](#method.get_flags) if you're interested only in modifiable flags
     pub fn get_all_flags(&self) -> MdbResult<EnvCreateFlags> {
         let mut flags: c_uint = 0;
-        lift_mdb!(unsafe {ffi::mdb_env_get_flags(self.env.0, &mut flags)}, EnvCreateFlags::from_bits_truncate(flags))
+        lift_mdb!(unsafe {ffi::mdb_env_get_flags(self.env.ptr, &mut flags)}, EnvCreateFlags::from_bits_truncate(flags))
     }
 
     pub fn get_maxreaders(&self) -> MdbResult<c_uint> {
         let mut max_readers: c_uint = 0;
         lift_mdb!(unsafe {
-            ffi::mdb_env_get_maxreaders(self.env.0, &mut max_readers)
+            ffi::mdb_env_get_maxreaders(self.env.ptr, &mut max_readers)
         }, max_readers)
     }
 
     pub fn get_maxkeysize(&self) -> c_int {
-        unsafe {ffi::mdb_env_get_maxkeysize(self.env.0)}
+        unsafe {ffi::mdb_env_get_maxkeysize(self.env.ptr)}
     }
 
     /// Creates a backup copy in specified file descriptor
     pub fn copy_to_fd(&self, fd: ffi::mdb_filehandle_t) -> MdbResult<()> {
-        lift_mdb!(unsafe { ffi::mdb_env_copyfd(self.env.0, fd) })
+        lift_mdb!(unsafe { ffi::mdb_env_copyfd(self.env.ptr, fd) })
     }
 
     /// Gets file descriptor of this environment
     pub fn get_fd(&self) -> MdbResult<ffi::mdb_filehandle_t> {
         let mut fd = 0;
-        lift_mdb!({ unsafe { ffi::mdb_env_get_fd(self.env.0, &mut fd) }}, fd)
+        lift_mdb!({ unsafe { ffi::mdb_env_get_fd(self.env.ptr, &mut fd) }}, fd)
     }
 
     /// Creates a backup copy in specified path
     // FIXME: check who is responsible for creating path: callee or caller
     pub fn copy_to_path<P: AsRef<Path>>(&self, path: P) -> MdbResult<()> {
-        // FIXME: revert back once `convert` is stable
-        // let c_path = path.as_os_str().to_cstring().unwrap();
-        let path_str = path.as_ref().to_str().ok_or(MdbError::InvalidPath)?;
-        let c_path = CString::new(path_str).map_err(|_| MdbError::InvalidPath)?;
+        let c_path = path_to_cstring(path.as_ref()).ok_or(MdbError::InvalidPath)?;
 
         unsafe {
-            lift_mdb!(ffi::mdb_env_copy(self.env.0, c_path.as_ref().as_ptr()))
+            lift_mdb!(ffi::mdb_env_copy(self.env.ptr, c_path.as_ptr()))
         }
     }
 
@@ -819,12 +2219,55 @@ impl Environment {
             _ => ptr::null_mut()
         };
 
-        lift_mdb!(unsafe { ffi::mdb_txn_begin(self.env.0, parent_handle, flags, &mut handle) },
-                 NativeTransaction::new_with_handle(handle, flags as usize, self))
+        let mut attempts = 0;
+        loop {
+            let guard = self.env.txn_guard()?;
+            let res = unsafe { ffi::mdb_txn_begin(self.env.ptr, parent_handle, flags, &mut handle) };
+            if res == ffi::MDB_MAP_RESIZED && attempts < MAX_MAP_RESIZE_ATTEMPTS {
+                drop(guard);
+                attempts += 1;
+                self.adopt_map_size()?;
+                continue;
+            }
+            return lift_mdb!(res, NativeTransaction::new_with_handle(handle, flags as usize, self, guard));
+        }
+    }
+
+    /// Picks up map size grown by another process. It is possible only
+    /// when there are no active transactions in this process, otherwise
+    /// `MapResized` is returned.
+    fn adopt_map_size(&self) -> MdbResult<()> {
+        // never waits, as the thread itself may hold a transaction
+        let _guard = match self.env.txn_lock.try_write() {
+            Ok(guard) => guard,
+            Err(TryLockError::WouldBlock) => {
+                debug!("can't adopt new map size, {} transaction(s) active",
+                       self.env.live_txns.load(AtomicOrdering::SeqCst));
+                return Err(MapResized);
+            }
+            Err(TryLockError::Poisoned(_)) => {
+                return Err(MdbError::StateError("transaction lock poisoned".to_owned()));
+            }
+        };
+
+        try_mdb!(unsafe { ffi::mdb_env_set_mapsize(self.env.ptr, 0) });
+        self.env.map_resizes.fetch_add(1, AtomicOrdering::SeqCst);
+        info!("adopted map size grown by another process");
+        Ok(())
+    }
+
+    /// Returns how many times map size grown by another process was
+    /// adopted by `new_transaction` and `get_reader`
+    pub fn map_resize_count(&self) -> usize {
+        self.env.map_resizes.load(AtomicOrdering::SeqCst)
     }
 
     /// Creates a new read-write transaction
     ///
+    /// If map was grown by another process, new size is adopted
+    /// transparently as long as there are no other active transactions
+    /// in this process, `MapResized` is returned otherwise.
+    ///
     /// Use `get_reader` to get much faster lock-free alternative
     pub fn new_transaction(&self) -> MdbResult<Transaction> {
         if self.is_readonly {
@@ -833,12 +2276,13 @@ impl Environment {
         self.create_transaction(None, 0).map(Transaction::new_with_native)
     }
 
-    /// Creates a readonly transaction
+    /// Creates a readonly transaction, handles map resize the same
+    /// way as `new_transaction`
     pub fn get_reader(&self) -> MdbResult<ReadonlyTransaction> {
         self.create_transaction(None, ffi::MDB_RDONLY).map(ReadonlyTransaction::new_with_native)
     }
 
-    fn _open_db(&self, db_name: & str, flags: DbFlags, force_creation: bool) -> MdbResult<ffi::MDB_dbi> {
+    fn _open_db(&self, db_name: & str, flags: DbFlags, force_creation: bool) -> MdbResult<CachedDb> {
         debug!("Opening {} (create={}, read_only={})", db_name, force_creation, self.is_readonly);
         // From LMDB docs for mdb_dbi_open:
         //
@@ -848,42 +2292,22 @@ impl Environment {
         // this function
         match self.db_cache.lock() {
             Err(_) => Err(MdbError::CacheError),
-            Ok(guard) => {
-                let cell = &(guard);
-                let cache = cell.get();
-
-                unsafe {
-                    if let Some(db) = (*cache).get(db_name) {
-                        debug!("Cached value for {}: {}", db_name, *db);
-                        return Ok(*db);
-                    }
+            Ok(mut cache) => {
+                if let Some(db) = cache.get(db_name) {
+                    debug!("Cached value for {}: {}", db_name, db.handle);
+                    return db.check_flags(flags).map(|_| *db);
                 }
 
                 let mut txn = {
                     let txflags = if self.is_readonly { ffi::MDB_RDONLY } else { 0 };
                     self.create_transaction(None, txflags)?
                 };
-                let opt_name = if !db_name.is_empty() {Some(db_name)} else {None};
-                let flags = if force_creation {flags | DbFlags::DbCreate} else {flags - DbFlags::DbCreate};
-
-                let mut db: ffi::MDB_dbi = 0;
-                let db_res = match opt_name {
-                    None => unsafe { ffi::mdb_dbi_open(txn.handle, ptr::null(), flags.bits(), &mut db) },
-                    Some(db_name) => {
-                        let db_name = CString::new(db_name.as_bytes()).unwrap();
-                        unsafe {
-                            ffi::mdb_dbi_open(txn.handle, db_name.as_ptr(), flags.bits(), &mut db)
-                        }
-                    }
-                };
-
-                try_mdb!(db_res);
+                let db = txn.open_db(db_name, flags, force_creation)?;
                 txn.commit()?;
 
-                debug!("Caching: {} -> {}", db_name, db);
-                unsafe {
-                    (*cache).insert(db_name.to_owned(), db);
-                };
+                debug!("Caching: {} -> {}", db_name, db.handle);
+                self.env.db_opened(db.handle);
+                cache.insert(db_name.to_owned(), db);
 
                 Ok(db)
             }
@@ -891,15 +2315,20 @@ impl Environment {
     }
 
     /// Opens existing DB
+    ///
+    /// Fails with `FlagsMismatch` if DB was created with different flags
     pub fn get_db(& self, db_name: &str, flags: DbFlags) -> MdbResult<DbHandle> {
         let db = self._open_db(db_name, flags, false)?;
-        Ok(DbHandle {handle: db, flags })
+        Ok(self.env.db_handle(db))
     }
 
     /// Opens or creates a DB
+    ///
+    /// Fails with `FlagsMismatch` if DB exists and was created with
+    /// different flags
     pub fn create_db(&self, db_name: &str, flags: DbFlags) -> MdbResult<DbHandle> {
         let db = self._open_db(db_name, flags, true)?;
-        Ok(DbHandle {handle: db, flags })
+        Ok(self.env.db_handle(db))
     }
 
     /// Opens default DB with specified flags
@@ -907,66 +2336,294 @@ impl Environment {
         self.get_db("", flags)
     }
 
-    fn drop_db_from_cache(&self, handle: ffi::MDB_dbi) {
-        match self.db_cache.lock() {
-            Err(_) => (),
-            Ok(guard) => {
-                let cell = &(guard);
-
-                unsafe {
-                    let cache = cell.get();
-
-                    let mut key = None;
-                    for (k, v) in (*cache).iter() {
-                        if *v == handle {
-                            key = Some(k);
-                            break;
-                        }
-                    }
+    /// Runs `f` in a new transaction while holding db cache lock, as
+    /// `mdb_dbi_open` can't be used by concurrent transactions. Handles
+    /// pushed to transaction's pending list are cached on commit
+    fn with_db_cache<T, F>(&self, f: F) -> MdbResult<T>
+        where F: FnOnce(&NativeTransaction, &DbCache) -> MdbResult<T> {
+        let mut cache = self.db_cache.lock().map_err(|_| MdbError::CacheError)?;
+        let mut txn = {
+            let txflags = if self.is_readonly { ffi::MDB_RDONLY } else { 0 };
+            self.create_transaction(None, txflags)?
+        };
 
-                    if let Some(key) = key {
-                        (*cache).remove(key);
-                    }
-                }
-            }
+        let res = f(&txn, &cache)?;
+
+        // taken out as commit would try to lock the cache
+        let dbs = txn.pending_dbs.take();
+        txn.commit()?;
+        for (db_name, db) in dbs.into_iter() {
+            debug!("Caching: {} -> {}", db_name, db.handle);
+            self.env.db_opened(db.handle);
+            cache.insert(db_name, db);
         }
+        Ok(res)
     }
-}
 
-unsafe impl Sync for Environment {}
-unsafe impl Send for Environment {}
+    /// Lists named databases along with their flags and statistics
+    ///
+    /// Databases are read from records of the main database without
+    /// being opened, keys of it which are not databases are skipped.
+    pub fn list_dbs(&self) -> MdbResult<Vec<DbInfo>> {
+        let txn = self.create_transaction(None, ffi::MDB_RDONLY)?;
+        txn.db_records()
+    }
 
-impl Clone for Environment {
-    fn clone(&self) -> Environment {
-        Environment {
-            env: self.env.clone(),
-            db_cache: self.db_cache.clone(),
-            is_readonly: self.is_readonly,
+    /// Renames a database by copying all of its items into a new one
+    /// and dropping the old one in a single transaction
+    ///
+    /// Fails with `KeyExists` if database `new_name` exists. Handles to
+    /// the old database must not be used afterwards. Comparators of the
+    /// old database are installed on the new one, fails with `StateError`
+    /// if builder sets different ones for `new_name`.
+    pub fn rename_db(&self, old_name: &str, new_name: &str) -> MdbResult<()> {
+        if self.is_readonly {
+            return Err(MdbError::StateError("Error: renaming db in read-only environment".to_owned()))
         }
+        let old = self.with_db_cache(|txn, cache| {
+            let old = txn.open_cached_db(cache, old_name)?;
+            let new = txn.create_new_db(new_name, old.flags)?;
+            self.env.carry_comparators(txn.handle, new.handle, &self.env, old.handle)?;
+            txn.copy_items(txn, old.handle, new.handle)?;
+            try_mdb!(unsafe { ffi::mdb_drop(txn.handle, old.handle, 1) });
+            Ok(old)
+        })?;
+        self.drop_db_from_cache(old.handle);
+        Ok(())
     }
-}
 
-#[allow(dead_code)]
-#[derive(Copy, Clone, Debug)]
-/// A handle to a database
-///
-/// It can be cached to avoid opening db on every access
-/// In the current state it is unsafe as other thread
-/// can ask to drop it.
-pub struct DbHandle {
-    handle: ffi::MDB_dbi,
-    flags: DbFlags
-}
+    /// Copies database `src_name` into a new database `dst_name` of
+    /// `dst_env`, which could be this environment as well
+    ///
+    /// The new database has the same flags and comparators and is
+    /// written in a single transaction, fails with `KeyExists` if it
+    /// exists and with `StateError` if builder of `dst_env` sets
+    /// different comparators for `dst_name`.
+    pub fn copy_db(&self, src_name: &str, dst_env: &Environment, dst_name: &str) -> MdbResult<()> {
+        if dst_env.is_readonly {
+            return Err(MdbError::StateError("Error: copying db to read-only environment".to_owned()))
+        }
 
-unsafe impl Sync for DbHandle {}
-unsafe impl Send for DbHandle {}
+        if Arc::ptr_eq(&self.env, &dst_env.env) {
+            return self.with_db_cache(|txn, cache| {
+                let src = txn.open_cached_db(cache, src_name)?;
+                let dst = txn.create_new_db(dst_name, src.flags)?;
+                self.env.carry_comparators(txn.handle, dst.handle, &self.env, src.handle)?;
+                txn.copy_items(txn, src.handle, dst.handle)
+            });
+        }
 
-#[derive(Copy, PartialEq, Debug, Eq, Clone)]
-enum TransactionState {
-    Normal,   // Normal, any operation possible
-    Released, // Released (reset on readonly), has to be renewed
-    Invalid,  // Invalid, no further operation possible
-}
+        // only one cache lock is held at a time, so copying in
+        // opposite directions can't deadlock
+        let src = self.with_db_cache(|txn, cache| txn.open_cached_db(cache, src_name))?;
+        let reader = self.create_transaction(None, ffi::MDB_RDONLY)?;
+        dst_env.with_db_cache(|txn, _| {
+            let dst = txn.create_new_db(dst_name, src.flags)?;
+            dst_env.env.carry_comparators(txn.handle, dst.handle, &self.env, src.handle)?;
+            txn.copy_items(&reader, src.handle, dst.handle)
+        })
+    }
+
+    /// Caches handles opened by a committed transaction
+    fn cache_dbs(&self, dbs: Vec<(String, CachedDb)>) {
+        if dbs.is_empty() {
+            return;
+        }
+        match self.db_cache.lock() {
+            Err(_) => error!("failed to cache {} db handle(s), cache lock poisoned", dbs.len()),
+            Ok(mut cache) => {
+                for (db_name, db) in dbs.into_iter() {
+                    debug!("Caching: {} -> {}", db_name, db.handle);
+                    self.env.db_opened(db.handle);
+                    cache.insert(db_name, db);
+                }
+            }
+        }
+    }
+
+    fn drop_db_from_cache(&self, handle: ffi::MDB_dbi) {
+        if let Ok(mut cache) = self.db_cache.lock() {
+            cache.retain(|_, v| v.handle != handle);
+        }
+        self.env.db_closed(handle);
+    }
+
+    /// Closes db handle, it and all of its copies become stale
+    ///
+    /// As the handle might be used by any transaction, it can be closed
+    /// only while there are none active, otherwise `StateError` is
+    /// returned. Normally handles don't need to be closed as they are
+    /// released along with environment.
+    pub fn close_db(&self, handle: DbHandle) -> MdbResult<()> {
+        // mdb_dbi_close must not overlap with mdb_dbi_open
+        let mut cache = self.db_cache.lock().map_err(|_| MdbError::CacheError)?;
+        self.env.check_db(handle.handle, handle.generation)?;
+
+        // no transaction may begin until db is closed
+        let _guard = match self.env.txn_lock.try_write() {
+            Ok(guard) => guard,
+            Err(TryLockError::WouldBlock) => {
+                let active = self.env.live_txns.load(AtomicOrdering::SeqCst);
+                return Err(MdbError::StateError(format!("can't close db while {} transaction(s) active", active)));
+            }
+            Err(TryLockError::Poisoned(_)) => {
+                return Err(MdbError::StateError("transaction lock poisoned".to_owned()));
+            }
+        };
+
+        cache.retain(|_, v| v.handle != handle.handle);
+        self.env.db_closed(handle.handle);
+        unsafe { ffi::mdb_dbi_close(self.env.ptr, handle.handle) };
+        Ok(())
+    }
+
+    /// Closes environment.
+    ///
+    /// Environment is closed only if this is the last copy of it, otherwise
+    /// `EnvInUse` is returned with the count of other copies, transactions
+    /// and cursors still alive, and environment will be closed when the
+    /// last of them is dropped.
+    pub fn close(self) -> MdbResult<()> {
+        let env = self.env;
+        match Arc::try_unwrap(env) {
+            Ok(handle) => {
+                debug!("closing environment");
+                drop(handle);
+                Ok(())
+            },
+            Err(env) => Err(EnvInUse {
+                environments: Arc::strong_count(&env) - 1,
+                transactions: env.live_txns.load(AtomicOrdering::SeqCst),
+                cursors: env.live_cursors.load(AtomicOrdering::SeqCst),
+            })
+        }
+    }
+}
+
+impl Clone for Environment {
+    fn clone(&self) -> Environment {
+        Environment {
+            env: self.env.clone(),
+            db_cache: self.db_cache.clone(),
+            is_readonly: self.is_readonly,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+/// A handle to a database
+///
+/// It can be cached to avoid opening db on every access.
+/// Once db is dropped or handle is closed, using any copy
+/// of it fails with `StaleHandle`.
+pub struct DbHandle {
+    handle: ffi::MDB_dbi,
+    flags: DbFlags,
+    generation: usize,
+}
+
+unsafe impl Sync for DbHandle {}
+unsafe impl Send for DbHandle {}
+
+impl DbHandle {
+    /// Flags the database was created with
+    pub fn flags(&self) -> DbFlags {
+        self.flags
+    }
+}
+
+/// Named database as listed by `Environment::list_dbs`
+#[derive(Clone)]
+pub struct DbInfo {
+    pub name: String,
+    pub flags: DbFlags,
+    pub stat: ffi::MDB_stat,
+}
+
+impl std::fmt::Debug for DbInfo {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        fmt.debug_struct("DbInfo")
+            .field("name", &self.name)
+            .field("flags", &self.flags)
+            .field("entries", &self.stat.ms_entries)
+            .finish()
+    }
+}
+
+/// Record main db keeps for a named database, mirrors `MDB_db` of
+/// LMDB built without `MDB_VL32`
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct DbRecord {
+    pad: u32,
+    flags: u16,
+    depth: u16,
+    branch_pages: usize,
+    leaf_pages: usize,
+    overflow_pages: usize,
+    entries: usize,
+    root: usize,
+}
+
+impl DbRecord {
+    /// Reads record from value of main db, `None` if it can't be one,
+    /// i.e. value is plain data stored in main db
+    fn parse(value: &[u8]) -> Option<DbRecord> {
+        if value.len() != std::mem::size_of::<DbRecord>() {
+            return None;
+        }
+        let record: DbRecord = unsafe { ptr::read_unaligned(value.as_ptr() as *const DbRecord) };
+        let flags = DbFlags::from_bits(c_uint::from(record.flags))?;
+        // empty db has no root page, any other has one
+        let is_empty = record.root == usize::MAX;
+        let is_valid = flags == flags.persistent()
+            && is_empty == (record.depth == 0)
+            && is_empty == (record.entries == 0);
+        if is_valid { Some(record) } else { None }
+    }
+
+    fn db_flags(&self) -> DbFlags {
+        DbFlags::from_bits_truncate(c_uint::from(self.flags))
+    }
+
+    fn stat(&self, psize: c_uint) -> ffi::MDB_stat {
+        ffi::MDB_stat {
+            ms_psize: psize,
+            ms_depth: c_uint::from(self.depth),
+            ms_branch_pages: self.branch_pages,
+            ms_leaf_pages: self.leaf_pages,
+            ms_overflow_pages: self.overflow_pages,
+            ms_entries: self.entries,
+        }
+    }
+}
+
+/// Handle opened by environment along with its actual flags
+#[derive(Copy, Clone, Debug)]
+struct CachedDb {
+    handle: ffi::MDB_dbi,
+    flags: DbFlags,
+}
+
+impl CachedDb {
+    fn check_flags(&self, requested: DbFlags) -> MdbResult<()> {
+        let requested = requested.persistent();
+        if requested == self.flags {
+            Ok(())
+        } else {
+            Err(FlagsMismatch { requested, actual: self.flags })
+        }
+    }
+}
+
+type DbCache = HashMap<String, CachedDb>;
+
+#[derive(Copy, PartialEq, Debug, Eq, Clone)]
+enum TransactionState {
+    Normal,   // Normal, any operation possible
+    Released, // Released (reset on readonly), has to be renewed
+    Invalid,  // Invalid, no further operation possible
+}
 
 #[derive(Debug)]
 struct NativeTransaction<'a> {
@@ -974,16 +2631,26 @@ struct NativeTransaction<'a> {
     env: &'a Environment,
     flags: usize,
     state: TransactionState,
+    parent: Option<&'a NativeTransaction<'a>>,
+    // dbs opened in this transaction, cached by env on commit
+    pending_dbs: RefCell<Vec<(String, CachedDb)>>,
+    // keeps map from being resized under the transaction
+    _txn_guard: RwLockReadGuard<'a, ()>,
 }
 
 impl<'a> NativeTransaction<'a> {
-    fn new_with_handle(h: *mut ffi::MDB_txn, flags: usize, env: &Environment) -> NativeTransaction {
+    fn new_with_handle<'e>(h: *mut ffi::MDB_txn, flags: usize, env: &'e Environment,
+                           guard: RwLockReadGuard<'e, ()>) -> NativeTransaction<'e> {
         // debug!("new native txn");
+        env.env.live_txns.fetch_add(1, AtomicOrdering::SeqCst);
         NativeTransaction {
             handle: h,
             flags,
             state: TransactionState::Normal,
             env,
+            parent: None,
+            pending_dbs: RefCell::new(Vec::new()),
+            _txn_guard: guard,
         }
     }
 
@@ -1000,6 +2667,12 @@ impl<'a> NativeTransaction<'a> {
             TransactionState::Invalid
         };
         try_mdb!(unsafe { ffi::mdb_txn_commit(self.handle) } );
+
+        let dbs = self.pending_dbs.take();
+        match self.parent {
+            Some(parent) => parent.pending_dbs.borrow_mut().extend(dbs),
+            None => self.env.cache_dbs(dbs),
+        }
         Ok(())
     }
 
@@ -1038,8 +2711,141 @@ impl<'a> NativeTransaction<'a> {
 
     fn new_child(&self, flags: c_uint) -> MdbResult<NativeTransaction> {
         let mut out: *mut ffi::MDB_txn = ptr::null_mut();
+        let guard = self.env.env.txn_guard()?;
         try_mdb!(unsafe { ffi::mdb_txn_begin(ffi::mdb_txn_env(self.handle), self.handle, flags, &mut out) });
-        Ok(NativeTransaction::new_with_handle(out, flags as usize, self.env))
+        let mut child = NativeTransaction::new_with_handle(out, flags as usize, self.env, guard);
+        child.parent = Some(self);
+        Ok(child)
+    }
+
+    /// Opens db in this transaction, the handle becomes invalid
+    /// if transaction is aborted
+    fn open_db(&self, db_name: &str, flags: DbFlags, force_creation: bool) -> MdbResult<CachedDb> {
+        assert_state_eq!(txn, self.state, TransactionState::Normal);
+        let requested = flags.persistent();
+        let mut db: ffi::MDB_dbi = 0;
+
+        if db_name.is_empty() {
+            // LMDB merges requested flags into the ones of the main db,
+            // so they are applied only while it is still empty
+            try_mdb!(unsafe { ffi::mdb_dbi_open(self.handle, ptr::null(), 0, &mut db) });
+            let actual = self.db_flags(db)?;
+            if actual != requested {
+                if !actual.is_empty() || self.stat(db)?.ms_entries > 0 {
+                    return Err(FlagsMismatch { requested, actual });
+                }
+                try_mdb!(unsafe { ffi::mdb_dbi_open(self.handle, ptr::null(), requested.bits(), &mut db) });
+            }
+            self.env.env.set_db_comparators(self.handle, db_name, db)?;
+            return Ok(CachedDb { handle: db, flags: requested });
+        }
+
+        let flags = if force_creation {flags | DbFlags::DbCreate} else {flags - DbFlags::DbCreate};
+        // flags of existing db are silently ignored by LMDB
+        let db = self.open_named_db(db_name, flags)?;
+        db.check_flags(requested)?;
+        Ok(db)
+    }
+
+    /// Opens named db with whatever flags it was created with
+    fn open_named_db(&self, db_name: &str, flags: DbFlags) -> MdbResult<CachedDb> {
+        let c_name = CString::new(db_name.as_bytes())
+            .map_err(|_| MdbError::StateError(format!("invalid db name {:?}", db_name)))?;
+        let mut db: ffi::MDB_dbi = 0;
+        try_mdb!(unsafe { ffi::mdb_dbi_open(self.handle, c_name.as_ptr(), flags.bits(), &mut db) });
+        self.env.env.set_db_comparators(self.handle, db_name, db)?;
+        Ok(CachedDb { handle: db, flags: self.db_flags(db)? })
+    }
+
+    /// Looks up existing db in cache or opens it, newly opened
+    /// handle is cached on commit
+    fn open_cached_db(&self, cache: &DbCache, db_name: &str) -> MdbResult<CachedDb> {
+        if let Some(db) = cache.get(db_name) {
+            return Ok(*db);
+        }
+        let db = self.open_named_db(db_name, DbFlags::empty())?;
+        self.pending_dbs.borrow_mut().push((db_name.to_owned(), db));
+        Ok(db)
+    }
+
+    /// Lists databases recorded in main db
+    fn db_records(&self) -> MdbResult<Vec<DbInfo>> {
+        let mut main: ffi::MDB_dbi = 0;
+        try_mdb!(unsafe { ffi::mdb_dbi_open(self.handle, ptr::null(), 0, &mut main) });
+        let psize = self.stat(main)?.ms_psize;
+
+        let mut dbs = Vec::new();
+        let mut cursor: Cursor<ReadWrite> = Cursor::new(self, main)?;
+        let mut res = cursor.to_first();
+        while res.is_ok() {
+            let (name, value): (Vec<u8>, Vec<u8>) = cursor.get()?;
+            // such names can't be passed to mdb_dbi_open anyway
+            let name = match String::from_utf8(name) {
+                Ok(name) if !name.is_empty() && !name.contains('\0') => name,
+                _ => {
+                    res = cursor.to_next_key();
+                    continue;
+                }
+            };
+            if let Some(record) = DbRecord::parse(&value) {
+                dbs.push(DbInfo { name, flags: record.db_flags(), stat: record.stat(psize) });
+            }
+            res = cursor.to_next_key();
+        }
+        match res {
+            Err(NotFound) | Ok(_) => Ok(dbs),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Copies all items of `src` db, read by `src_txn`, into empty
+    /// `dst` db
+    ///
+    /// Items come sorted, so they are appended without lookups, which
+    /// requires `dst` to use the same comparators.
+    fn copy_items(&self, src_txn: &NativeTransaction, src: ffi::MDB_dbi, dst: ffi::MDB_dbi) -> MdbResult<()> {
+        let has_dups = src_txn.db_flags(src)?.contains(DbFlags::DbAllowDups);
+        let mut cursor: Cursor<ReadWrite> = Cursor::new(src_txn, src)?;
+        let mut res = cursor.to_first();
+        while res.is_ok() {
+            let (key, value) = cursor.get_plain()?;
+            self.set_value_with_flags(dst, &key, &value, ffi::MDB_APPEND)?;
+            if has_dups {
+                loop {
+                    match cursor.navigate(ffi::MDB_cursor_op::MDB_NEXT_DUP) {
+                        Ok(()) => (),
+                        Err(NotFound) => break,
+                        Err(e) => return Err(e),
+                    }
+                    let (key, value) = cursor.get_plain()?;
+                    self.set_value_with_flags(dst, &key, &value, ffi::MDB_APPENDDUP)?;
+                }
+            }
+            res = cursor.to_next_key();
+        }
+        match res {
+            Err(NotFound) | Ok(_) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Creates `db_name` with provided flags, fails with `KeyExists`
+    /// if it is already there
+    fn create_new_db(&self, db_name: &str, flags: DbFlags) -> MdbResult<CachedDb> {
+        match self.open_named_db(db_name, DbFlags::empty()) {
+            Ok(_) => return Err(KeyExists),
+            Err(NotFound) => (),
+            Err(e) => return Err(e),
+        }
+        let db = self.open_named_db(db_name, flags.persistent() | DbFlags::DbCreate)?;
+        self.pending_dbs.borrow_mut().push((db_name.to_owned(), db));
+        Ok(db)
+    }
+
+    /// Retrieves flags db was created with
+    fn db_flags(&self, db: ffi::MDB_dbi) -> MdbResult<DbFlags> {
+        let mut flags: c_uint = 0;
+        lift_mdb!(unsafe { ffi::mdb_dbi_flags(self.handle, db, &mut flags) }, DbFlags::from_bits_truncate(flags))
     }
 
     /// Used in Drop to switch state
@@ -1051,30 +2857,42 @@ impl<'a> NativeTransaction<'a> {
         }
     }
 
-    fn get_value<V: FromMdbValue + 'a>(&'a self, db: ffi::MDB_dbi, key: &dyn ToMdbValue) -> MdbResult<V> {
-        let mut key_val = key.to_mdb_value();
+    fn get_value<M: TxnMode, V: FromTxnValue<'a, M>>(&'a self, db: ffi::MDB_dbi, key: &dyn ToMdbValue) -> MdbResult<V> {
+        let key_val = key.to_mdb_value();
         unsafe {
-            let mut data_val: MdbValue = std::mem::zeroed();
-            try_mdb!(ffi::mdb_get(self.handle, db, &mut key_val.value, &mut data_val.value));
-            Ok(FromMdbValue::from_mdb_value(&data_val))
+            let mut data_val: ffi::MDB_val = std::mem::zeroed();
+            try_mdb!(ffi::mdb_get(self.handle, db, &mut key_val.raw(), &mut data_val));
+            Ok(FromTxnValue::from_txn_value(MdbValue::from_raw(&data_val).data()))
         }
     }
 
-    fn get<V: FromMdbValue + 'a>(&'a self, db: ffi::MDB_dbi, key: &dyn ToMdbValue) -> MdbResult<V> {
+    fn get<M: TxnMode, V: FromTxnValue<'a, M>>(&'a self, db: ffi::MDB_dbi, key: &dyn ToMdbValue) -> MdbResult<V> {
         assert_state_eq!(txn, self.state, TransactionState::Normal);
         self.get_value(db, key)
     }
 
+    /// Retrieves value without decoding it, `None` if key doesn't exist
+    fn get_raw(&self, db: ffi::MDB_dbi, key: &dyn ToMdbValue) -> MdbResult<Option<ffi::MDB_val>> {
+        assert_state_eq!(txn, self.state, TransactionState::Normal);
+        let key_val = key.to_mdb_value();
+        let mut data_val: ffi::MDB_val = unsafe { std::mem::zeroed() };
+        match unsafe { ffi::mdb_get(self.handle, db, &mut key_val.raw(), &mut data_val) } {
+            ffi::MDB_SUCCESS => Ok(Some(data_val)),
+            ffi::MDB_NOTFOUND => Ok(None),
+            e => Err(MdbError::new_with_code(e)),
+        }
+    }
+
     fn set_value(&self, db: ffi::MDB_dbi, key: &dyn ToMdbValue, value: &dyn ToMdbValue) -> MdbResult<()> {
         self.set_value_with_flags(db, key, value, 0)
     }
 
     fn set_value_with_flags(&self, db: ffi::MDB_dbi, key: &dyn ToMdbValue, value: &dyn ToMdbValue, flags: c_uint) -> MdbResult<()> {
         unsafe {
-            let mut key_val = key.to_mdb_value();
-            let mut data_val = value.to_mdb_value();
+            let key_val = key.to_mdb_value();
+            let data_val = value.to_mdb_value();
 
-            lift_mdb!(ffi::mdb_put(self.handle, db, &mut key_val.value, &mut data_val.value, flags))
+            lift_mdb!(ffi::mdb_put(self.handle, db, &mut key_val.raw(), &mut data_val.raw(), flags))
         }
     }
 
@@ -1097,6 +2915,21 @@ impl<'a> NativeTransaction<'a> {
         self.set_value_with_flags(db, key, value, ffi::MDB_APPENDDUP)
     }
 
+    fn put_with_flags(&self, db: ffi::MDB_dbi, key: &dyn ToMdbValue, value: &dyn ToMdbValue, flags: WriteFlags) -> MdbResult<()> {
+        assert_state_eq!(txn, self.state, TransactionState::Normal);
+        self.set_value_with_flags(db, key, value, flags.bits())
+    }
+
+    fn reserve<F: FnOnce(&mut [u8])>(&self, db: ffi::MDB_dbi, key: &dyn ToMdbValue, len: usize, fill: F) -> MdbResult<()> {
+        assert_state_eq!(txn, self.state, TransactionState::Normal);
+        let key_val = key.to_mdb_value();
+        let mut data_val = MDB_val { mv_data: ptr::null(), mv_size: len };
+        try_mdb!(unsafe { ffi::mdb_put(self.handle, db, &mut key_val.raw(), &mut data_val, ffi::MDB_RESERVE) });
+        // LMDB left data_val pointing to the reserved space
+        fill(unsafe { std::slice::from_raw_parts_mut(data_val.mv_data as *mut u8, len) });
+        Ok(())
+    }
+
     /// Set the value for key only if the key does not exist in the database,
     /// even if the database supports duplicates.
     fn insert(&self, db: ffi::MDB_dbi, key: &dyn ToMdbValue, value: &dyn ToMdbValue) -> MdbResult<()> {
@@ -1107,8 +2940,8 @@ impl<'a> NativeTransaction<'a> {
     /// Deletes all values by key
     fn del_value(&self, db: ffi::MDB_dbi, key: &dyn ToMdbValue) -> MdbResult<()> {
         unsafe {
-            let mut key_val = key.to_mdb_value();
-            lift_mdb!(ffi::mdb_del(self.handle, db, &mut key_val.value, ptr::null_mut()))
+            let key_val = key.to_mdb_value();
+            lift_mdb!(ffi::mdb_del(self.handle, db, &mut key_val.raw(), ptr::null_mut()))
         }
     }
 
@@ -1116,10 +2949,10 @@ impl<'a> NativeTransaction<'a> {
     fn del_item(&self, db: ffi::MDB_dbi, key: &dyn ToMdbValue, data: &dyn ToMdbValue) -> MdbResult<()> {
         assert_state_eq!(txn, self.state, TransactionState::Normal);
         unsafe {
-            let mut key_val = key.to_mdb_value();
-            let mut data_val = data.to_mdb_value();
+            let key_val = key.to_mdb_value();
+            let data_val = data.to_mdb_value();
 
-            lift_mdb!(ffi::mdb_del(self.handle, db, &mut key_val.value, &mut data_val.value))
+            lift_mdb!(ffi::mdb_del(self.handle, db, &mut key_val.raw(), &mut data_val.raw()))
         }
     }
 
@@ -1130,17 +2963,18 @@ impl<'a> NativeTransaction<'a> {
     }
 
     /// Creates a new cursor in current transaction tied to db
-    fn new_cursor(&'a self, db: ffi::MDB_dbi) -> MdbResult<Cursor<'a>> {
+    fn new_cursor<M: TxnMode>(&'a self, db: ffi::MDB_dbi) -> MdbResult<Cursor<'a, M>> {
         Cursor::new(self, db)
     }
 
     /// Deletes provided database completely
-    fn del_db(&self, db: Database) -> MdbResult<()> {
+    fn del_db<M: TxnMode>(&self, db: Database<M>) -> MdbResult<()> {
         assert_state_eq!(txn, self.state, TransactionState::Normal);
-        unsafe {
-            self.env.drop_db_from_cache(db.handle);
-            lift_mdb!(ffi::mdb_drop(self.handle, db.handle, 1))
-        }
+        let dbi = db.dbi()?;
+        // LMDB closes the handle right away
+        try_mdb!(unsafe { ffi::mdb_drop(self.handle, dbi, 1) });
+        self.env.drop_db_from_cache(dbi);
+        Ok(())
     }
 
     /// Empties provided database
@@ -1151,6 +2985,23 @@ impl<'a> NativeTransaction<'a> {
         }
     }
 
+    /// Compares keys with db comparator
+    fn cmp_keys(&self, db: ffi::MDB_dbi, a: &MdbValue, b: &MdbValue) -> Ordering {
+        let (mut a, mut b) = (a.raw(), b.raw());
+        unsafe { ffi::mdb_cmp(self.handle, db, &mut a, &mut b) }.cmp(&0)
+    }
+
+    fn cmp_items(&self, db: ffi::MDB_dbi, a: &MdbValue, b: &MdbValue) -> Ordering {
+        let (mut a, mut b) = (a.raw(), b.raw());
+        unsafe { ffi::mdb_dcmp(self.handle, db, &mut a, &mut b) }.cmp(&0)
+    }
+
+    /// Id of the snapshot a read-only transaction sees
+    #[cfg_attr(not(feature = "rayon"), allow(dead_code))]
+    fn id(&self) -> usize {
+        unsafe { ffi::mdb_txn_id(self.handle) }
+    }
+
     /// Retrieves provided database's statistics
     fn stat(&self, db: ffi::MDB_dbi) -> MdbResult<ffi::MDB_stat> {
         let mut tmp: ffi::MDB_stat = unsafe { std::mem::zeroed() };
@@ -1175,6 +3026,13 @@ impl<'a> Drop for NativeTransaction<'a> {
     fn drop(&mut self) {
         //debug!("Dropping native transaction!");
         self.silent_abort();
+        // LMDB closes dbs opened by not committed transaction
+        for (_, db) in self.pending_dbs.take() {
+            if !self.env.env.is_db_open(db.handle) {
+                self.env.env.db_closed(db.handle);
+            }
+        }
+        self.env.env.live_txns.fetch_sub(1, AtomicOrdering::SeqCst);
     }
 }
 
@@ -1211,8 +3069,31 @@ impl<'a> Transaction<'a> {
         t.inner.abort();
     }
 
-    pub fn bind(&self, db_handle: &DbHandle) -> Database {
-        Database::new_with_handle(db_handle.handle, &self.inner)
+    pub fn bind(&self, db_handle: &DbHandle) -> Database<ReadWrite> {
+        Database::new_with_handle(db_handle, &self.inner)
+    }
+
+    fn _open_db(&self, db_name: &str, flags: DbFlags, force_creation: bool) -> MdbResult<DbHandle> {
+        debug!("Opening {} in txn (create={})", db_name, force_creation);
+        let db = self.inner.open_db(db_name, flags, force_creation)?;
+        self.inner.pending_dbs.borrow_mut().push((db_name.to_owned(), db));
+        Ok(self.inner.env.env.db_handle(db))
+    }
+
+    /// Opens existing DB within this transaction.
+    ///
+    /// Unlike `Environment::get_db` it doesn't start another transaction,
+    /// so it is safe to call while holding the write lock. Handle is
+    /// added to environment's cache only when transaction is committed,
+    /// it becomes invalid if transaction is aborted.
+    pub fn open_db(&self, db_name: &str, flags: DbFlags) -> MdbResult<DbHandle> {
+        self._open_db(db_name, flags, false)
+    }
+
+    /// Opens or creates a DB within this transaction, so that DB and its
+    /// first data are created atomically. See `open_db`.
+    pub fn create_db(&self, db_name: &str, flags: DbFlags) -> MdbResult<DbHandle> {
+        self._open_db(db_name, flags, true)
     }
 }
 
@@ -1253,8 +3134,8 @@ impl<'a> ReadonlyTransaction<'a> {
         self.inner.renew()
     }
 
-    pub fn bind(&self, db_handle: &DbHandle) -> Database {
-        Database::new_with_handle(db_handle.handle, &self.inner)
+    pub fn bind(&self, db_handle: &DbHandle) -> Database<ReadOnly> {
+        Database::new_with_handle(db_handle, &self.inner)
     }
 }
 
@@ -1279,22 +3160,25 @@ impl IsLess for MdbResult<Ordering> {
     }
 }
 
+/// Cursor, `M` is the mode of the transaction it belongs to
 #[derive(Debug)]
-pub struct Cursor<'txn> {
+pub struct Cursor<'txn, M> {
     handle: *mut ffi::MDB_cursor,
     data_val: ffi::MDB_val,
     key_val: ffi::MDB_val,
     txn: &'txn NativeTransaction<'txn>,
     db: ffi::MDB_dbi,
     valid_key: bool,
+    marker: ::std::marker::PhantomData<M>,
 }
 
 
-impl<'txn> Cursor<'txn> {
-    fn new(txn: &'txn NativeTransaction, db: ffi::MDB_dbi) -> MdbResult<Cursor<'txn>> {
+impl<'txn, M: TxnMode> Cursor<'txn, M> {
+    fn new(txn: &'txn NativeTransaction, db: ffi::MDB_dbi) -> MdbResult<Cursor<'txn, M>> {
         debug!("Opening cursor in {}", db);
         let mut tmp: *mut ffi::MDB_cursor = std::ptr::null_mut();
         try_mdb!(unsafe { ffi::mdb_cursor_open(txn.handle, db, &mut tmp) });
+        txn.env.env.live_cursors.fetch_add(1, AtomicOrdering::SeqCst);
         Ok(Cursor {
             handle: tmp,
             data_val: unsafe { std::mem::zeroed() },
@@ -1302,6 +3186,7 @@ impl<'txn> Cursor<'txn> {
             txn,
             db,
             valid_key: false,
+            marker: ::std::marker::PhantomData,
         })
     }
 
@@ -1313,13 +3198,15 @@ impl<'txn> Cursor<'txn> {
         };
         match res {
             ffi::MDB_SUCCESS => {
-                // MDB_SET is the only cursor operation which doesn't
-                // writes back a new value. In this case any access to
+                // MDB_SET, MDB_GET_BOTH and MDB_GET_BOTH_RANGE don't
+                // write back a new key. In this case any access to
                 // cursor key value should cause a cursor retrieval
                 // to get back pointer to database owned memory instead
                 // of value used to set the cursor as it might be
                 // already destroyed and there is no need to borrow it
-                self.valid_key = op != ffi::MDB_cursor_op::MDB_SET;
+                self.valid_key = !matches!(op, ffi::MDB_cursor_op::MDB_SET
+                                           | ffi::MDB_cursor_op::MDB_GET_BOTH
+                                           | ffi::MDB_cursor_op::MDB_GET_BOTH_RANGE);
                 Ok(())
             },
             e => Err(MdbError::new_with_code(e))
@@ -1328,9 +3215,12 @@ impl<'txn> Cursor<'txn> {
 
     fn move_to<K, V>(&mut self, key: &K, value: Option<&V>, op: ffi::MDB_cursor_op) -> MdbResult<()>
         where K: ToMdbValue, V: ToMdbValue {
-        self.key_val = key.to_mdb_value().value;
+        // both have to stay in place until LMDB is done with them
+        let key = key.to_mdb_value();
+        let value = value.map(|v| v.to_mdb_value());
+        self.key_val = key.raw();
         self.data_val = match value {
-            Some(v) => v.to_mdb_value().value,
+            Some(ref v) => v.raw(),
             _ => unsafe {std::mem::zeroed() }
         };
 
@@ -1403,40 +3293,105 @@ impl<'txn> Cursor<'txn> {
     }
 
     /// Retrieves current key/value as tuple
-    pub fn get<'a, T: FromMdbValue + 'a, U: FromMdbValue + 'a>(&'a mut self) -> MdbResult<(T, U)> {
+    pub fn get<T: FromTxnValue<'txn, M>, U: FromTxnValue<'txn, M>>(&mut self) -> MdbResult<(T, U)> {
         let (k, v) = self.get_plain()?;
-
-        unsafe {
-            Ok((FromMdbValue::from_mdb_value(mem::transmute::<&MdbValue<'_>, &MdbValue<'_>>(&k)),
-                FromMdbValue::from_mdb_value(mem::transmute::<&MdbValue<'_>, &MdbValue<'_>>(&v))))
-        }
+        Ok((FromTxnValue::from_txn_value(k.data()), FromTxnValue::from_txn_value(v.data())))
     }
 
     /// Retrieves current value
-    pub fn get_value<'a, V: FromMdbValue + 'a>(&'a mut self) -> MdbResult<V> {
+    pub fn get_value<V: FromTxnValue<'txn, M>>(&mut self) -> MdbResult<V> {
         let (_, v) = self.get_plain()?;
-
-        unsafe {
-            Ok(FromMdbValue::from_mdb_value(mem::transmute::<&MdbValue<'_>, &MdbValue<'_>>(&v)))
-        }
+        Ok(FromTxnValue::from_txn_value(v.data()))
     }
 
     /// Retrieves current key
-    pub fn get_key<'a, K: FromMdbValue + 'a>(&'a mut self) -> MdbResult<K> {
+    pub fn get_key<K: FromTxnValue<'txn, M>>(&mut self) -> MdbResult<K> {
         let (k, _) = self.get_plain()?;
+        Ok(FromTxnValue::from_txn_value(k.data()))
+    }
 
-        unsafe {
-            Ok(FromMdbValue::from_mdb_value(mem::transmute::<&MdbValue<'_>, &MdbValue<'_>>(&k)))
+    /// Turns result of a cursor move into the entry it landed at,
+    /// `None` if there is no such entry
+    fn found<K, V>(&mut self, res: MdbResult<()>) -> MdbResult<Option<(K, V)>>
+        where K: FromTxnValue<'txn, M>, V: FromTxnValue<'txn, M> {
+        match res {
+            Ok(()) => self.get().map(Some),
+            Err(NotFound) => Ok(None),
+            Err(e) => Err(e),
         }
     }
 
+    /// Moves cursor to the first entry with key greater than or equal
+    /// to `key` and returns it
+    pub fn seek_ceil<K, V>(&mut self, key: &dyn ToMdbValue) -> MdbResult<Option<(K, V)>>
+        where K: FromTxnValue<'txn, M>, V: FromTxnValue<'txn, M> {
+        let res = self.to_gte_key(&key.to_mdb_value());
+        self.found(res)
+    }
+
+    /// Moves cursor to the entry with greatest key less than or equal
+    /// to `key` and returns it. In case of DbAllowDups it is the first
+    /// item of `key` itself or the last item of a smaller key.
+    pub fn seek_floor<K, V>(&mut self, key: &dyn ToMdbValue) -> MdbResult<Option<(K, V)>>
+        where K: FromTxnValue<'txn, M>, V: FromTxnValue<'txn, M> {
+        let key = key.to_mdb_value();
+        let res = match self.to_gte_key(&key) {
+            Ok(()) if self.cmp_key(&key)? == Ordering::Equal => Ok(()),
+            Ok(()) => self.navigate(ffi::MDB_cursor_op::MDB_PREV),
+            // every key is less
+            Err(NotFound) => self.to_last(),
+            Err(e) => Err(e),
+        };
+        self.found(res)
+    }
+
+    /// Moves cursor to the first entry of `key` and returns it
+    pub fn seek_exact<K, V>(&mut self, key: &dyn ToMdbValue) -> MdbResult<Option<(K, V)>>
+        where K: FromTxnValue<'txn, M>, V: FromTxnValue<'txn, M> {
+        let res = self.to_key(&key.to_mdb_value());
+        self.found(res)
+    }
+
+    /// Moves cursor to the first entry and returns it
+    pub fn first<K, V>(&mut self) -> MdbResult<Option<(K, V)>>
+        where K: FromTxnValue<'txn, M>, V: FromTxnValue<'txn, M> {
+        let res = self.to_first();
+        self.found(res)
+    }
+
+    /// Moves cursor to the last entry and returns it
+    pub fn last<K, V>(&mut self) -> MdbResult<Option<(K, V)>>
+        where K: FromTxnValue<'txn, M>, V: FromTxnValue<'txn, M> {
+        let res = self.to_last();
+        self.found(res)
+    }
+
+    /// Moves cursor to the next entry, including items with duplicate
+    /// keys, and returns it. Unpositioned cursor moves to the first one.
+    // fallible and generic over decoded types, so not an Iterator
+    #[allow(clippy::should_implement_trait)]
+    pub fn next<K, V>(&mut self) -> MdbResult<Option<(K, V)>>
+        where K: FromTxnValue<'txn, M>, V: FromTxnValue<'txn, M> {
+        let res = self.navigate(ffi::MDB_cursor_op::MDB_NEXT);
+        self.found(res)
+    }
+
+    /// Moves cursor to the previous entry, including items with duplicate
+    /// keys, and returns it. Unpositioned cursor moves to the last one.
+    pub fn prev<K, V>(&mut self) -> MdbResult<Option<(K, V)>>
+        where K: FromTxnValue<'txn, M>, V: FromTxnValue<'txn, M> {
+        let res = self.navigate(ffi::MDB_cursor_op::MDB_PREV);
+        self.found(res)
+    }
+
     /// Compares the cursor's current key with the specified other one.
     #[inline]
     fn cmp_key(&mut self, other: &MdbValue) -> MdbResult<Ordering> {
         let (k, _) = self.get_plain()?;
-        let mut kval = k.value;
+        let mut kval = k.raw();
+        let mut other = other.raw();
         let cmp = unsafe {
-            ffi::mdb_cmp(self.txn.handle, self.db, &mut kval, mem::transmute(other))
+            ffi::mdb_cmp(self.txn.handle, self.db, &mut kval, &mut other)
         };
         Ok(match cmp {
             n if n < 0 => Ordering::Less,
@@ -1445,6 +3400,13 @@ impl<'txn> Cursor<'txn> {
         })
     }
 
+    /// Compares the cursor's current item with the specified other one.
+    #[inline]
+    fn cmp_item(&mut self, other: &MdbValue) -> MdbResult<Ordering> {
+        let (_, v) = self.get_plain()?;
+        Ok(self.txn.cmp_items(self.db, &v, other))
+    }
+
     #[inline]
     fn ensure_key_valid(&mut self) -> MdbResult<()> {
         // If key might be invalid simply perform cursor get to be sure
@@ -1460,11 +3422,19 @@ impl<'txn> Cursor<'txn> {
         Ok(())
     }
 
+    /// Key and value at cursor, unlike `get_plain` nothing is cached
+    fn current(&self) -> MdbResult<(MdbValue<'txn>, MdbValue<'txn>)> {
+        let mut key: MDB_val = unsafe { std::mem::zeroed() };
+        let mut data: MDB_val = unsafe { std::mem::zeroed() };
+        try_mdb!(unsafe { ffi::mdb_cursor_get(self.handle, &mut key, &mut data, ffi::MDB_cursor_op::MDB_GET_CURRENT) });
+        Ok(unsafe { (MdbValue::from_raw(&key), MdbValue::from_raw(&data)) })
+    }
+
     #[inline]
     fn get_plain(&mut self) -> MdbResult<(MdbValue<'txn>, MdbValue<'txn>)> {
         self.ensure_key_valid()?;
-        let k = MdbValue {value: self.key_val, marker: ::std::marker::PhantomData};
-        let v = MdbValue {value: self.data_val, marker: ::std::marker::PhantomData};
+        let k = unsafe { MdbValue::from_raw(&self.key_val) };
+        let v = unsafe { MdbValue::from_raw(&self.data_val) };
 
         Ok((k, v))
     }
@@ -1479,16 +3449,31 @@ impl<'txn> Cursor<'txn> {
         }
     }
 
-    fn set_value<V: ToMdbValue>(&mut self, value: &V, flags: c_uint) -> MdbResult<()> {
+    /// Writes value for the current key
+    fn set_value<V: ToMdbValue>(&mut self, value: &V, flags: WriteFlags) -> MdbResult<()> {
         self.ensure_key_valid()?;
-        self.data_val = value.to_mdb_value().value;
-        lift_mdb!(unsafe {ffi::mdb_cursor_put(self.handle, &mut self.key_val, &mut self.data_val, flags)})
+        // current key lives in the page LMDB is about to rewrite, so
+        // it has to be copied out first
+        let key = unsafe { as_slice(&self.key_val) }.to_vec();
+        self.key_val = key.to_mdb_value().raw();
+        let res = self.put(value, flags);
+        self.valid_key = false;
+        res
+    }
+
+    fn put<V: ToMdbValue>(&mut self, value: &V, flags: WriteFlags) -> MdbResult<()> {
+        let value = value.to_mdb_value();
+        self.data_val = value.raw();
+        lift_mdb!(unsafe {ffi::mdb_cursor_put(self.handle, &mut self.key_val, &mut self.data_val, flags.bits())})
     }
 
-    pub fn set<K: ToMdbValue, V: ToMdbValue>(&mut self, key: &K, value: &V, flags: c_uint) -> MdbResult<()> {
-        self.key_val = key.to_mdb_value().value;
-        self.valid_key = true;
-        let res = self.set_value(value, flags);
+    /// Sets value for key the way `flags` tell, fails with
+    /// `InvalidWriteFlags` if they don't fit the database
+    pub fn set<K: ToMdbValue, V: ToMdbValue>(&mut self, key: &K, value: &V, flags: WriteFlags) -> MdbResult<()> {
+        flags.check(self.txn.db_flags(self.db)?, true)?;
+        let key = key.to_mdb_value();
+        self.key_val = key.raw();
+        let res = self.put(value, flags);
         self.valid_key = false;
         res
     }
@@ -1496,14 +3481,33 @@ impl<'txn> Cursor<'txn> {
     /// Overwrites value for current item
     /// Note: overwrites max cur_value.len() bytes
     pub fn replace<V: ToMdbValue>(&mut self, value: &V) -> MdbResult<()> {
-        let res = self.set_value(value, ffi::MDB_CURRENT);
-        self.valid_key = false;
-        res
+        self.set_value(value, WriteFlags::Current)
     }
 
     /// Adds a new item when created with allowed duplicates
     pub fn add_item<V: ToMdbValue>(&mut self, value: &V) -> MdbResult<()> {
-        let res = self.set_value(value, 0);
+        self.set_value(value, WriteFlags::empty())
+    }
+
+    /// Stores `items`, a sequence of items `item_size` bytes each,
+    /// for key at once. Only for DbDupFixed databases, returns
+    /// number of items written.
+    pub fn set_multiple<K: ToMdbValue>(&mut self, key: &K, items: &[u8], item_size: usize) -> MdbResult<usize> {
+        if !self.txn.db_flags(self.db)?.contains(DbFlags::DbDupFixed) {
+            return Err(InvalidWriteFlags { flags: WriteFlags::Multiple, reason: "Multiple requires a DbDupFixed db" });
+        }
+        if item_size == 0 || items.len() % item_size != 0 {
+            return Err(StateError(format!("{} bytes aren't a whole number of {} byte items", items.len(), item_size)));
+        }
+        let key = key.to_mdb_value();
+        self.key_val = key.raw();
+        let mut data = [
+            ffi::MDB_val { mv_data: items.as_ptr() as *const c_void, mv_size: item_size },
+            ffi::MDB_val { mv_data: ptr::null(), mv_size: items.len() / item_size },
+        ];
+        let res = lift_mdb!(unsafe {
+            ffi::mdb_cursor_put(self.handle, &mut self.key_val, data.as_mut_ptr(), ffi::MDB_MULTIPLE)
+        }, data[1].mv_size);
         self.valid_key = false;
         res
     }
@@ -1512,319 +3516,1275 @@ impl<'txn> Cursor<'txn> {
         lift_mdb!(unsafe { ffi::mdb_cursor_del(self.handle, flags) })
     }
 
-    /// Deletes current key
-    pub fn del(&mut self) -> MdbResult<()> {
-        self.del_all()
-    }
+    /// Deletes current key
+    pub fn del(&mut self) -> MdbResult<()> {
+        self.del_all()
+    }
+
+    /// Deletes only current item
+    ///
+    /// Note that it doesn't check anything so it is caller responsibility
+    /// to make sure that correct item is deleted if, for example, caller
+    /// wants to delete only items of current key
+    pub fn del_item(&mut self) -> MdbResult<()> {
+        let res = self.del_value(0);
+        self.valid_key = false;
+        res
+    }
+
+    /// Deletes all items with same key as current
+    pub fn del_all(&mut self) -> MdbResult<()> {
+        self.del_value(ffi::MDB_NODUPDATA)
+    }
+
+    /// Returns count of items with the same key as current
+    pub fn item_count(&self) -> MdbResult<size_t> {
+        let mut tmp: size_t = 0;
+        lift_mdb!(unsafe {ffi::mdb_cursor_count(self.handle, &mut tmp)}, tmp)
+    }
+
+    pub fn get_item<'k, K: ToMdbValue>(self, k: &'k K) -> CursorItemAccessor<'txn, 'k, K, M> {
+        CursorItemAccessor {
+            cursor: self,
+            key: k
+        }
+    }
+}
+
+impl<'txn, M> Drop for Cursor<'txn, M> {
+    fn drop(&mut self) {
+        unsafe { ffi::mdb_cursor_close(self.handle) };
+        self.txn.env.env.live_cursors.fetch_sub(1, AtomicOrdering::SeqCst);
+    }
+}
+
+#[derive(Debug)]
+pub struct CursorItemAccessor<'c, 'k, K: 'k, M> {
+    cursor: Cursor<'c, M>,
+    key: &'k K,
+}
+
+impl<'k, 'c: 'k, K: ToMdbValue, M: TxnMode> CursorItemAccessor<'c, 'k, K, M> {
+    pub fn get<V: FromTxnValue<'c, M>>(&mut self) -> MdbResult<V> {
+        self.cursor.to_key(self.key)?;
+        self.cursor.get_value()
+    }
+
+    pub fn add<V: ToMdbValue>(&mut self, v: &V) -> MdbResult<()> {
+        self.cursor.set(self.key, v, WriteFlags::empty())
+    }
+
+    pub fn del<V: ToMdbValue>(&mut self, v: &V) -> MdbResult<()> {
+        self.cursor.to_item(self.key, v)?;
+        self.cursor.del_item()
+    }
+
+    pub fn del_all(&mut self) -> MdbResult<()> {
+        self.cursor.to_key(self.key)?;
+        self.cursor.del_all()
+    }
+
+    pub fn into_inner(self) -> Cursor<'c, M> {
+        let tmp = self;
+        tmp.cursor
+    }
+}
+
+
+/// Data kept by lazy wrappers such as `CursorValue` to be decoded
+/// later. A write transaction may rewrite or free pages as soon as
+/// anything is written, so there the data is copied out right away.
+#[derive(Debug, Clone)]
+enum HeldValue<'txn> {
+    Borrowed(MdbValue<'txn>),
+    Owned(Vec<u8>),
+}
+
+impl<'txn> HeldValue<'txn> {
+    fn new<M: TxnMode>(value: MdbValue<'txn>) -> HeldValue<'txn> {
+        if M::COPIES_DATA {
+            HeldValue::Owned(value.as_bytes().to_vec())
+        } else {
+            HeldValue::Borrowed(value)
+        }
+    }
+
+    /// Value to decode. Copies are only made in write transactions,
+    /// which decode to owned types only, so nothing decoded from the
+    /// value keeps pointing to the copy.
+    fn view(&self) -> MdbValue<'txn> {
+        match *self {
+            HeldValue::Borrowed(value) => value,
+            HeldValue::Owned(ref bytes) => unsafe { MdbValue::new(bytes.as_ptr() as *const c_void, bytes.len()) },
+        }
+    }
+
+    fn decode<M: TxnMode, T: FromTxnValue<'txn, M>>(&self) -> T {
+        FromTxnValue::from_txn_value(self.view().data())
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        match *self {
+            HeldValue::Borrowed(ref value) => value.as_bytes(),
+            HeldValue::Owned(ref bytes) => bytes,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct CursorValue<'txn, M> {
+    key: HeldValue<'txn>,
+    value: HeldValue<'txn>,
+    marker: ::std::marker::PhantomData<M>,
+}
+
+/// CursorValue performs lazy data extraction from iterator
+/// avoiding any data conversions and memory copy. Borrowed
+/// data is limited to transaction lifetime, so it stays valid
+/// after the iterator moves on. In write transactions the item
+/// is copied when yielded, as writes may change it afterwards.
+impl<'txn, M: TxnMode> CursorValue<'txn, M> {
+    fn new(key: MdbValue<'txn>, value: MdbValue<'txn>) -> CursorValue<'txn, M> {
+        CursorValue {
+            key: HeldValue::new::<M>(key),
+            value: HeldValue::new::<M>(value),
+            marker: ::std::marker::PhantomData,
+        }
+    }
+
+    pub fn get_key<T: FromTxnValue<'txn, M>>(&self) -> T {
+        self.key.decode()
+    }
+
+    pub fn get_value<T: FromTxnValue<'txn, M>>(&self) -> T {
+        self.value.decode()
+    }
+
+    pub fn get<T: FromTxnValue<'txn, M>, U: FromTxnValue<'txn, M>>(&self) -> (T, U) {
+        (self.key.decode(), self.value.decode())
+    }
+}
+
+/// Allows the cration of custom cursor iteration behaviours.
+pub trait IterateCursor {
+    /// Returns true if initialization successful, for example that
+    /// the key exists.
+    fn init_cursor<M: TxnMode>(&self, cursor: &mut Cursor<M>) -> bool;
+
+    /// Returns true if there is still data and iterator is in correct range
+    fn move_to_next<M: TxnMode>(&self, cursor: &mut Cursor<M>) -> bool;
+
+    /// Returns size hint right after `init_cursor` positioned the
+    /// cursor successfully, iterator keeps it up to date afterwards.
+    /// Unless the hint is exact, iterator counts items left by walking
+    /// a second cursor the first time it is asked for the hint.
+    fn get_size_hint<M: TxnMode>(&self, _cursor: &Cursor<M>) -> (usize, Option<usize>) {
+        (0, None)
+    }
+}
+
+
+#[derive(Debug)]
+pub struct CursorIterator<'c, I, M> {
+    inner: I,
+    has_data: bool,
+    // count of items left, once known
+    remaining: Cell<Option<usize>>,
+    cursor: Cursor<'c, M>,
+    marker: ::std::marker::PhantomData<&'c ()>,
+}
+
+impl<'c, I: IterateCursor, M: TxnMode> CursorIterator<'c, I, M> {
+    fn wrap(cursor: Cursor<'c, M>, inner: I) -> CursorIterator<'c, I, M> {
+        let mut cursor = cursor;
+        let has_data = inner.init_cursor(&mut cursor);
+        let remaining = match inner.get_size_hint(&cursor) {
+            _ if !has_data => Some(0),
+            (lower, Some(upper)) if lower == upper => Some(upper),
+            _ => None,
+        };
+        CursorIterator {
+            inner,
+            has_data,
+            remaining: Cell::new(remaining),
+            cursor,
+            marker: ::std::marker::PhantomData,
+        }
+    }
+
+    #[allow(dead_code)]
+    fn unwrap(self) -> Cursor<'c, M> {
+        self.cursor
+    }
+
+    /// Counts items left, the current one included, with a second
+    /// cursor so that the iterator itself stays where it is
+    fn count_remaining(&self) -> MdbResult<usize> {
+        let (key, value) = self.cursor.current()?;
+        let mut walker = Cursor::<M>::new(self.cursor.txn, self.cursor.db)?;
+        // the item itself is sought only with duplicates, as LMDB
+        // doesn't compare values of other dbs
+        if self.cursor.txn.db_flags(self.cursor.db)?.contains(DbFlags::DbAllowDups) {
+            walker.to_item(&key, &value)?;
+        } else {
+            walker.to_key(&key)?;
+        }
+        let mut count = 1;
+        while self.inner.move_to_next(&mut walker) {
+            count += 1;
+        }
+        Ok(count)
+    }
+}
+
+impl<'c, I: IterateCursor, M: TxnMode> Iterator for CursorIterator<'c, I, M> {
+    type Item = CursorValue<'c, M>;
+
+    fn next(&mut self) -> Option<CursorValue<'c, M>> {
+        if !self.has_data {
+            None
+        } else {
+            match self.cursor.get_plain() {
+                Err(_) => None,
+                Ok((k, v)) => {
+                    self.has_data = self.inner.move_to_next(&mut self.cursor);
+                    let remaining = if self.has_data {
+                        self.remaining.get().map(|n| n.saturating_sub(1))
+                    } else {
+                        Some(0)
+                    };
+                    self.remaining.set(remaining);
+                    Some(CursorValue::new(k, v))
+                }
+            }
+        }
+    }
+
+    /// Exact count of items left. Ranges and keys of databases with
+    /// duplicates are counted on first call, which takes a walk over
+    /// what is left to iterate.
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = match self.remaining.get() {
+            Some(n) => Some(n),
+            None => {
+                let counted = self.count_remaining().ok();
+                self.remaining.set(counted);
+                counted
+            }
+        };
+        match remaining {
+            Some(n) => (n, Some(n)),
+            None => (0, None),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct CursorKeyRangeIter<'a> {
+    start_key: MdbValue<'a>,
+    end_key: MdbValue<'a>,
+    end_inclusive: bool,
+    marker: ::std::marker::PhantomData<&'a ()>,
+}
+
+impl<'a> CursorKeyRangeIter<'a> {
+    pub fn new<K: ToMdbValue+'a>(start_key: &'a K, end_key: &'a K, end_inclusive: bool) -> CursorKeyRangeIter<'a> {
+        CursorKeyRangeIter {
+            start_key: start_key.to_mdb_value(),
+            end_key: end_key.to_mdb_value(),
+            end_inclusive,
+            marker: ::std::marker::PhantomData,
+        }
+    }
+}
+
+impl<'iter> IterateCursor for CursorKeyRangeIter<'iter> {
+    fn init_cursor<M: TxnMode>(&self, cursor: &mut Cursor<M>) -> bool {
+        let ok = cursor.to_gte_key(&self.start_key).is_ok();
+        ok && cursor.cmp_key(&self.end_key).is_less(self.end_inclusive)
+    }
+
+    fn move_to_next<M: TxnMode>(&self, cursor: &mut Cursor<M>) -> bool {
+        let moved = cursor.to_next_key().is_ok();
+        if !moved {
+            false
+        } else {
+            cursor.cmp_key(&self.end_key).is_less(self.end_inclusive)
+        }
+    }
+
+}
+
+#[derive(Debug)]
+pub struct CursorFromKeyIter<'a> {
+    start_key: MdbValue<'a>,
+    marker: ::std::marker::PhantomData<&'a ()>,
+}
+
+
+impl<'a> CursorFromKeyIter<'a> {
+    pub fn new<K: ToMdbValue+'a>(start_key: &'a K) -> CursorFromKeyIter<'a> {
+        CursorFromKeyIter {
+            start_key: start_key.to_mdb_value(),
+            marker: ::std::marker::PhantomData
+        }
+    }
+}
+
+impl<'iter> IterateCursor for CursorFromKeyIter<'iter> {
+    fn init_cursor<M: TxnMode>(&self, cursor: &mut Cursor<M>) -> bool {
+        cursor.to_gte_key(&self.start_key).is_ok()
+    }
+
+    fn move_to_next<M: TxnMode>(&self, cursor: &mut Cursor<M>) -> bool {
+        cursor.to_next_key().is_ok()
+    }
+
+}
+
+
+#[derive(Debug)]
+pub struct CursorToKeyIter<'a> {
+    end_key: MdbValue<'a>,
+    marker: ::std::marker::PhantomData<&'a ()>,
+}
+
+
+impl<'a> CursorToKeyIter<'a> {
+    pub fn new<K: ToMdbValue+'a>(end_key: &'a K) -> CursorToKeyIter<'a> {
+        CursorToKeyIter {
+            end_key: end_key.to_mdb_value(),
+            marker: ::std::marker::PhantomData,
+        }
+    }
+}
+
+impl<'iter> IterateCursor for CursorToKeyIter<'iter> {
+    fn init_cursor<M: TxnMode>(&self, cursor: &mut Cursor<M>) -> bool {
+        let ok = cursor.to_first().is_ok();
+        ok && cursor.cmp_key(&self.end_key).is_less(false)
+    }
+
+    fn move_to_next<M: TxnMode>(&self, cursor: &mut Cursor<M>) -> bool {
+        let moved = cursor.to_next_key().is_ok();
+        if !moved {
+            false
+        } else {
+            cursor.cmp_key(&self.end_key).is_less(false)
+        }
+    }
+
+}
+
+#[allow(missing_copy_implementations)]
+#[derive(Debug)]
+pub struct CursorIter;
+
+
+impl IterateCursor for CursorIter {
+    fn init_cursor<M: TxnMode>(&self, cursor: &mut Cursor<M>) -> bool {
+        cursor.to_first().is_ok()
+    }
+
+    fn move_to_next<M: TxnMode>(&self, cursor: &mut Cursor<M>) -> bool {
+        cursor.to_next_key().is_ok()
+    }
+
+    fn get_size_hint<M: TxnMode>(&self, cursor: &Cursor<M>) -> (usize, Option<usize>) {
+        // every item is a key unless duplicates are allowed
+        match cursor.txn.db_flags(cursor.db) {
+            Ok(flags) if !flags.contains(DbFlags::DbAllowDups) => match cursor.txn.stat(cursor.db) {
+                Ok(stat) => (stat.ms_entries, Some(stat.ms_entries)),
+                Err(_) => (0, None),
+            },
+            _ => (0, None),
+        }
+    }
+}
+
+
+#[derive(Debug)]
+pub struct CursorItemIter<'a> {
+    key: MdbValue<'a>,
+    marker: ::std::marker::PhantomData<&'a ()>,
+}
+
+
+impl<'a> CursorItemIter<'a> {
+    pub fn new<K: ToMdbValue+'a>(key: &'a K) -> CursorItemIter<'a> {
+        CursorItemIter {
+            key: key.to_mdb_value(),
+            marker: ::std::marker::PhantomData
+        }
+    }
+}
+
+impl<'iter> IterateCursor for CursorItemIter<'iter> {
+    fn init_cursor<M: TxnMode>(&self, cursor: &mut Cursor<M>) -> bool {
+        cursor.to_key(&self.key).is_ok()
+    }
+
+    fn move_to_next<M: TxnMode>(&self, cursor: &mut Cursor<M>) -> bool {
+        cursor.to_next_item().is_ok()
+    }
+
+    fn get_size_hint<M: TxnMode>(&self, c: &Cursor<M>) -> (usize, Option<usize>) {
+        match c.item_count() {
+            Ok(cnt) => (cnt, Some(cnt)),
+            // not a DbAllowDups db, so the only item
+            Err(_) => (1, Some(1)),
+        }
+    }
+}
+
+
+#[derive(Debug, PartialEq)]
+enum IterMutState {
+    Start,
+    Next,
+    Done,
+}
+
+/// Write iterator created by `Database::iter_mut` and `range_mut`.
+/// It isn't an `Iterator` as every entry borrows it until dropped:
+///
+/// ```
+/// # use kn0sys_lmdb_rs::{EnvBuilder, DbFlags};
+/// # let dir = std::env::temp_dir().join("lmdb-rs-doc-iter-mut");
+/// # let env = EnvBuilder::new().open(&dir, 0o777).unwrap();
+/// # let db_handle = env.get_default_db(DbFlags::empty()).unwrap();
+/// # let txn = env.new_transaction().unwrap();
+/// # let db = txn.bind(&db_handle);
+/// # db.set(&"key", &"stale").unwrap();
+/// let mut iter = db.iter_mut().unwrap();
+/// while let Some(entry) = iter.next().unwrap() {
+///     if entry.value::<String>() == "stale" {
+///         entry.delete().unwrap();
+///     }
+/// }
+/// ```
+#[derive(Debug)]
+pub struct CursorIterMut<'txn> {
+    cursor: Cursor<'txn, ReadWrite>,
+    dups: bool,
+    start: Bound<Vec<u8>>,
+    end: Bound<Vec<u8>>,
+    state: IterMutState,
+}
+
+impl<'txn> CursorIterMut<'txn> {
+    /// Moves to the next item and returns a guard for it, `None`
+    /// when the range is over
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> MdbResult<Option<EntryMut<'_, 'txn>>> {
+        let res = match self.state {
+            IterMutState::Done => return Ok(None),
+            IterMutState::Start => match self.start {
+                Bound::Unbounded => self.cursor.to_first(),
+                Bound::Included(ref start) => self.cursor.to_gte_key(start),
+                Bound::Excluded(ref start) => match self.cursor.to_gte_key(start) {
+                    Ok(()) if self.cursor.cmp_key(&start.to_mdb_value())? == Ordering::Equal => self.cursor.to_next_key(),
+                    res => res,
+                },
+            },
+            // after mdb_cursor_del LMDB already points to the following
+            // item and knows that MDB_NEXT has to stay there
+            IterMutState::Next => self.cursor.navigate(ffi::MDB_cursor_op::MDB_NEXT),
+        };
+        self.state = IterMutState::Done;
+        match res {
+            Ok(()) => (),
+            Err(NotFound) => return Ok(None),
+            Err(e) => return Err(e),
+        }
+
+        let in_range = match self.end {
+            Bound::Unbounded => true,
+            Bound::Included(ref end) => self.cursor.cmp_key(&end.to_mdb_value())? != Ordering::Greater,
+            Bound::Excluded(ref end) => self.cursor.cmp_key(&end.to_mdb_value())? == Ordering::Less,
+        };
+        if !in_range {
+            return Ok(None);
+        }
+        self.state = IterMutState::Next;
+        let (key, value) = self.cursor.get_plain()?;
+        Ok(Some(EntryMut {
+            cursor: &mut self.cursor,
+            dups: self.dups,
+            key: HeldValue::new::<ReadWrite>(key),
+            value: HeldValue::new::<ReadWrite>(value),
+        }))
+    }
+}
+
+/// Item yielded by `CursorIterMut`, copied out when yielded
+#[derive(Debug)]
+pub struct EntryMut<'c, 'txn> {
+    cursor: &'c mut Cursor<'txn, ReadWrite>,
+    dups: bool,
+    key: HeldValue<'txn>,
+    value: HeldValue<'txn>,
+}
+
+impl<'c, 'txn> EntryMut<'c, 'txn> {
+    pub fn key<K: FromTxnValue<'txn, ReadWrite>>(&self) -> K {
+        self.key.decode()
+    }
+
+    pub fn value<V: FromTxnValue<'txn, ReadWrite>>(&self) -> V {
+        self.value.decode()
+    }
+
+    /// Deletes the item, iteration goes on with the following one
+    pub fn delete(self) -> MdbResult<()> {
+        self.cursor.del_item()
+    }
+
+    /// Replaces value of the item. In case of DbAllowDups the new item
+    /// has to sort the same as the old one, otherwise delete the old
+    /// item and add the new one after the iteration.
+    pub fn replace<V: ToMdbValue>(&mut self, value: &V) -> MdbResult<()> {
+        let new = value.to_mdb_value();
+        if self.dups && self.cursor.txn.cmp_items(self.cursor.db, &new, &self.value.view()) != Ordering::Equal {
+            return Err(StateError("replacing item would change its position among duplicates".to_owned()));
+        }
+        self.cursor.replace(value)?;
+        self.cursor.navigate(ffi::MDB_cursor_op::MDB_GET_CURRENT)?;
+        let (key, value) = self.cursor.get_plain()?;
+        self.key = HeldValue::new::<ReadWrite>(key);
+        self.value = HeldValue::new::<ReadWrite>(value);
+        Ok(())
+    }
+}
+
+
+/// Iterator over keys created by `Database::iter_groups`. It isn't an
+/// `Iterator` as every group borrows it until dropped:
+///
+/// ```
+/// # use kn0sys_lmdb_rs::{EnvBuilder, DbFlags};
+/// # let dir = std::env::temp_dir().join("lmdb-rs-doc-iter-groups");
+/// # let env = EnvBuilder::new().open(&dir, 0o777).unwrap();
+/// # let db_handle = env.get_default_db(DbFlags::DbAllowDups).unwrap();
+/// # let txn = env.new_transaction().unwrap();
+/// # let db = txn.bind(&db_handle);
+/// # db.set(&"key", &"value").unwrap();
+/// let mut groups = db.iter_groups().unwrap();
+/// while let Some(mut group) = groups.next().unwrap() {
+///     let key: String = group.key();
+///     let values: Vec<String> = group.items().map(|cv| cv.get_value()).collect();
+///     println!("{} -> {:?}", key, values);
+/// }
+/// ```
+#[derive(Debug)]
+pub struct GroupIter<'txn, M> {
+    cursor: Cursor<'txn, M>,
+    dups: bool,
+    started: bool,
+}
+
+impl<'txn, M: TxnMode> GroupIter<'txn, M> {
+    /// Moves to the next distinct key, `None` when there are no more
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> MdbResult<Option<Group<'_, 'txn, M>>> {
+        let res = if self.started {
+            self.cursor.to_next_key()
+        } else {
+            self.started = true;
+            self.cursor.to_first()
+        };
+        match res {
+            Ok(()) => {
+                let (key, _) = self.cursor.get_plain()?;
+                Ok(Some(Group { cursor: &mut self.cursor, dups: self.dups, key: HeldValue::new::<M>(key) }))
+            },
+            Err(NotFound) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Key yielded by `GroupIter` along with access to its items
+#[derive(Debug)]
+pub struct Group<'g, 'txn, M> {
+    cursor: &'g mut Cursor<'txn, M>,
+    dups: bool,
+    key: HeldValue<'txn>,
+}
+
+impl<'g, 'txn, M: TxnMode> Group<'g, 'txn, M> {
+    pub fn key<K: FromTxnValue<'txn, M>>(&self) -> K {
+        self.key.decode()
+    }
+
+    /// Returns count of items of the key
+    pub fn item_count(&self) -> MdbResult<usize> {
+        if self.dups { self.cursor.item_count() } else { Ok(1) }
+    }
+
+    /// Returns an iterator over items of the key, from the first one
+    /// whatever was iterated before
+    pub fn items(&mut self) -> GroupItems<'_, 'txn, M> {
+        let size_hint = match self.item_count() {
+            Ok(count) => (count, Some(count)),
+            Err(_) => (0, None),
+        };
+        GroupItems { cursor: &mut *self.cursor, dups: self.dups, started: false, done: false, size_hint }
+    }
+}
+
+/// Iterator over items of a `Group`
+#[derive(Debug)]
+pub struct GroupItems<'g, 'txn, M> {
+    cursor: &'g mut Cursor<'txn, M>,
+    dups: bool,
+    started: bool,
+    done: bool,
+    size_hint: (usize, Option<usize>),
+}
+
+impl<'g, 'txn, M: TxnMode> Iterator for GroupItems<'g, 'txn, M> {
+    type Item = CursorValue<'txn, M>;
+
+    fn next(&mut self) -> Option<CursorValue<'txn, M>> {
+        if self.done {
+            return None;
+        }
+        let op = match (self.started, self.dups) {
+            (false, true) => ffi::MDB_cursor_op::MDB_FIRST_DUP,
+            (false, false) => ffi::MDB_cursor_op::MDB_GET_CURRENT,
+            (true, true) => ffi::MDB_cursor_op::MDB_NEXT_DUP,
+            // the only item was yielded already
+            (true, false) => {
+                self.done = true;
+                return None;
+            },
+        };
+        self.started = true;
+        match self.cursor.navigate(op).and_then(|_| self.cursor.get_plain()) {
+            Ok((key, value)) => {
+                let (lower, upper) = self.size_hint;
+                self.size_hint = (lower.saturating_sub(1), upper.map(|n| n.saturating_sub(1)));
+                Some(CursorValue::new(key, value))
+            },
+            Err(_) => {
+                self.done = true;
+                self.size_hint = (0, Some(0));
+                None
+            },
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.size_hint
+    }
+}
+
+/// Keys `start <= key < end` of a `Database::split_ranges` part,
+/// `None` stands for no bound
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyRange {
+    pub start: Option<Vec<u8>>,
+    pub end: Option<Vec<u8>>,
+}
 
-    /// Deletes only current item
-    ///
-    /// Note that it doesn't check anything so it is caller responsibility
-    /// to make sure that correct item is deleted if, for example, caller
-    /// wants to delete only items of current key
-    pub fn del_item(&mut self) -> MdbResult<()> {
-        let res = self.del_value(0);
-        self.valid_key = false;
-        res
+/// Lets ranges go straight to `count_range`, `range_mut` and the like
+impl RangeBounds<Vec<u8>> for KeyRange {
+    fn start_bound(&self) -> Bound<&Vec<u8>> {
+        self.start.as_ref().map_or(Bound::Unbounded, Bound::Included)
     }
 
-    /// Deletes all items with same key as current
-    pub fn del_all(&mut self) -> MdbResult<()> {
-        self.del_value(ffi::MDB_NODUPDATA)
+    fn end_bound(&self) -> Bound<&Vec<u8>> {
+        self.end.as_ref().map_or(Bound::Unbounded, Bound::Excluded)
     }
+}
 
-    /// Returns count of items with the same key as current
-    pub fn item_count(&self) -> MdbResult<size_t> {
-        let mut tmp: size_t = 0;
-        lift_mdb!(unsafe {ffi::mdb_cursor_count(self.handle, &mut tmp)}, tmp)
+impl RangeBounds<Vec<u8>> for &KeyRange {
+    fn start_bound(&self) -> Bound<&Vec<u8>> {
+        (*self).start_bound()
     }
 
-    pub fn get_item<'k, K: ToMdbValue>(self, k: &'k K) -> CursorItemAccessor<'txn, 'k, K> {
-        CursorItemAccessor {
-            cursor: self,
-            key: k
-        }
+    fn end_bound(&self) -> Bound<&Vec<u8>> {
+        (*self).end_bound()
     }
 }
 
-impl<'txn> Drop for Cursor<'txn> {
-    fn drop(&mut self) {
-        unsafe { ffi::mdb_cursor_close(self.handle) };
+impl KeyRange {
+    /// Copies out every item within range
+    #[cfg(feature = "rayon")]
+    fn read(&self, db: &Database<ReadOnly>) -> MdbResult<Vec<(Vec<u8>, Vec<u8>)>> {
+        let mut cursor = db.cursor(db.dbi()?)?;
+        let mut res = match self.start {
+            Some(ref start) => cursor.to_gte_key(start),
+            None => cursor.to_first(),
+        };
+        let mut items = Vec::new();
+        loop {
+            match res {
+                Ok(()) => (),
+                Err(NotFound) => return Ok(items),
+                Err(e) => return Err(e),
+            }
+            if let Some(ref end) = self.end {
+                if cursor.cmp_key(&end.to_mdb_value())? != Ordering::Less {
+                    return Ok(items);
+                }
+            }
+            let (key, value) = cursor.get_plain()?;
+            items.push((key.as_bytes().to_vec(), value.as_bytes().to_vec()));
+            res = cursor.navigate(ffi::MDB_cursor_op::MDB_NEXT);
+        }
     }
 }
 
-#[derive(Debug)]
-pub struct CursorItemAccessor<'c, 'k, K: 'k> {
-    cursor: Cursor<'c>,
-    key: &'k K,
-}
+impl<'a> Database<'a, ReadWrite> {
+    /// Positions a cursor on key once and applies `f` to the current
+    /// value (the first item in case of DbAllowDups)
+    fn read_modify_write<K, R, F>(&self, key: &K, f: F) -> MdbResult<R>
+        where K: ToMdbValue, F: FnOnce(Option<&[u8]>) -> (Change, R) {
+        let mut cursor = self.cursor(self.dbi()?)?;
+        let found = match cursor.to_key(key) {
+            Ok(()) => true,
+            Err(NotFound) => false,
+            Err(e) => return Err(e),
+        };
 
-impl<'k, 'c: 'k, K: ToMdbValue> CursorItemAccessor<'c, 'k, K> {
-    pub fn get<'a, V: FromMdbValue + 'a>(&'a mut self) -> MdbResult<V> {
-        self.cursor.to_key(self.key)?;
-        self.cursor.get_value()
+        let current = if found { Some(unsafe { as_slice(&cursor.data_val) }) } else { None };
+        let (change, res) = f(current);
+        match change {
+            Change::Keep => (),
+            Change::Delete if found => cursor.del_item()?,
+            Change::Delete => (),
+            // MDB_CURRENT can't move an item among duplicates
+            Change::Put(value) if found && self.flags.contains(DbFlags::DbAllowDups) => {
+                cursor.del_item()?;
+                cursor.set(key, &value, WriteFlags::empty())?;
+            },
+            Change::Put(value) if found => cursor.replace(&value)?,
+            Change::Put(value) => cursor.set(key, &value, WriteFlags::empty())?,
+        }
+        Ok(res)
     }
 
-    pub fn add<V: ToMdbValue>(&mut self, v: &V) -> MdbResult<()> {
-        self.cursor.set(self.key, v, 0)
+    /// Replaces value for key with the one returned by `f`, which gets
+    /// the current value if there is one. Returning `None` deletes it.
+    ///
+    /// In case of DbAllowDups it acts on the first item of the key.
+    pub fn update<K, F>(&self, key: &K, f: F) -> MdbResult<()>
+        where K: ToMdbValue, F: FnOnce(Option<&[u8]>) -> Option<Vec<u8>> {
+        self.read_modify_write(key, |current| {
+            let change = match f(current) {
+                Some(value) => Change::Put(value),
+                None if current.is_some() => Change::Delete,
+                None => Change::Keep,
+            };
+            (change, ())
+        })
     }
 
-    pub fn del<V: ToMdbValue>(&mut self, v: &V) -> MdbResult<()> {
-        self.cursor.to_item(self.key, v)?;
-        self.cursor.del_item()
+    /// Retrieves a value by key, storing the one returned by `f` first
+    /// if there is none
+    pub fn get_or_insert_with<K, V, T, F>(&self, key: &K, f: F) -> MdbResult<V>
+        where K: ToMdbValue, V: FromTxnValue<'a, ReadWrite>, T: ToMdbValue, F: FnOnce() -> T {
+        let mut cursor = self.cursor(self.dbi()?)?;
+        match cursor.to_key(key) {
+            Ok(()) => (),
+            Err(NotFound) => {
+                cursor.set(key, &f(), WriteFlags::empty())?;
+                // value has to be read back from db memory
+                cursor.navigate(ffi::MDB_cursor_op::MDB_GET_CURRENT)?;
+            },
+            Err(e) => return Err(e),
+        }
+        let value = unsafe { MdbValue::from_raw(&cursor.data_val) };
+        Ok(FromTxnValue::from_txn_value(value.data()))
+    }
+
+    /// Sets value for key to `new` only if the current one is `expected`,
+    /// `None` standing for missing key in both cases. Returns whether the
+    /// value was swapped.
+    pub fn compare_and_swap<K, E, N>(&self, key: &K, expected: Option<&E>, new: Option<&N>) -> MdbResult<bool>
+        where K: ToMdbValue, E: ToMdbValue, N: ToMdbValue {
+        let expected = expected.map(|e| e.to_mdb_value());
+        let new = new.map(|n| n.to_mdb_value());
+        self.read_modify_write(key, |current| {
+            let expected = expected.as_ref().map(|e| e.as_bytes());
+            if current != expected {
+                return (Change::Keep, false);
+            }
+            let change = match new {
+                Some(ref new) => Change::Put(new.as_bytes().to_vec()),
+                None => Change::Delete,
+            };
+            (change, true)
+        })
     }
 
-    pub fn del_all(&mut self) -> MdbResult<()> {
-        self.cursor.to_key(self.key)?;
-        self.cursor.del_all()
+    /// Adds `delta` to a counter stored as big endian `i64`, the same as
+    /// `ToMdbValue for &i64` writes, a missing counter starts at 0.
+    /// Returns the new value.
+    pub fn counter_add<K: ToMdbValue>(&self, key: &K, delta: i64) -> MdbResult<i64> {
+        self.read_modify_write(key, |current| {
+            let current = match current {
+                None => 0,
+                Some(bytes) => match <[u8; 8]>::try_from(bytes) {
+                    Ok(bytes) => i64::from_be_bytes(bytes),
+                    Err(_) => return (Change::Keep, Err(MdbError::StateError(
+                        format!("counter value of {} bytes is not an i64", bytes.len())))),
+                },
+            };
+            match current.checked_add(delta) {
+                Some(value) => (Change::Put(value.to_be_bytes().to_vec()), Ok(value)),
+                None => (Change::Keep, Err(MdbError::StateError("counter overflow".to_owned()))),
+            }
+        })?
     }
 
-    pub fn into_inner(self) -> Cursor<'c> {
-        let tmp = self;
-        tmp.cursor
+    /// Returns a write iterator over all items, see `range_mut`
+    pub fn iter_mut(&self) -> MdbResult<CursorIterMut<'a>> {
+        self.range_mut::<&[u8], _>(..)
+    }
+
+    /// Returns a write iterator over items with keys in `range`. It
+    /// yields `EntryMut` guards which can delete or replace the item
+    /// they stand for without disturbing the iteration.
+    pub fn range_mut<K: ToMdbValue, R: RangeBounds<K>>(&self, range: R) -> MdbResult<CursorIterMut<'a>> {
+        let owned = |bound: Bound<&K>| bound.map(|k| k.to_mdb_value().as_bytes().to_vec());
+        Ok(CursorIterMut {
+            cursor: self.cursor(self.dbi()?)?,
+            dups: self.flags.contains(DbFlags::DbAllowDups),
+            start: owned(range.start_bound()),
+            end: owned(range.end_bound()),
+            state: IterMutState::Start,
+        })
     }
 }
 
+#[cfg(feature = "rayon")]
+impl<'a> Database<'a, ReadOnly> {
+    /// Reads all items in parallel, splitting keys by `split_ranges(n)`.
+    /// Every range is read by a rayon worker in its own
+    /// `ReadonlyTransaction`. A worker thread may hold a reader already,
+    /// so environment has to be opened with `EnvCreateNoTls`, otherwise
+    /// `StateError` is returned.
+    ///
+    /// LMDB can't open a reader on a given snapshot, so the snapshot is
+    /// neither pinned nor retried: if something is committed before all
+    /// workers got their readers, they see other data than the
+    /// transaction of this database and `StateError` is returned
+    /// instead of any items. The call can be repeated in a new
+    /// transaction then. To make sure of that, all ranges are read
+    /// before this returns, i.e. every item is copied out at once.
+    ///
+    /// Only for databases bound to a `ReadonlyTransaction`: workers
+    /// never see what a write transaction hasn't committed yet.
+    pub fn par_iter(&self, n: usize) -> MdbResult<impl rayon::iter::ParallelIterator<Item = (Vec<u8>, Vec<u8>)>> {
+        use rayon::prelude::*;
 
-#[derive(Debug)]
-pub struct CursorValue<'cursor> {
-    key: MdbValue<'cursor>,
-    value: MdbValue<'cursor>,
-    marker: ::std::marker::PhantomData<&'cursor ()>,
+        if !self.txn.env.get_all_flags()?.contains(EnvCreateFlags::EnvCreateNoTls) {
+            return Err(StateError("par_iter needs environment opened with EnvCreateNoTls".to_owned()));
+        }
+        let ranges = self.split_ranges(n)?;
+        let handle = DbHandle { handle: self.dbi()?, flags: self.flags, generation: self.generation };
+        let env = self.txn.env.clone();
+        let snapshot = self.txn.id();
+        let read = move |range: KeyRange| -> MdbResult<Vec<(Vec<u8>, Vec<u8>)>> {
+            let reader = env.get_reader()?;
+            let id = reader.inner.id();
+            if id != snapshot {
+                return Err(StateError(format!("snapshot {} isn't current anymore, worker got {}", snapshot, id)));
+            }
+            range.read(&reader.bind(&handle))
+        };
+        let items: Vec<_> = ranges.into_par_iter().map(read).collect::<MdbResult<_>>()?;
+        Ok(items.into_par_iter().flatten())
+    }
 }
 
-/// CursorValue performs lazy data extraction from iterator
-/// avoiding any data conversions and memory copy. Lifetime
-/// is limited to iterator lifetime
-impl<'cursor> CursorValue<'cursor> {
-    pub fn get_key<T: FromMdbValue + 'cursor>(&'cursor self) -> T {
-        FromMdbValue::from_mdb_value(&self.key)
-    }
+/// Databases with up to this many items are split by walking keys
+const SPLIT_WALK_MAX: usize = 4096;
+
+/// Keys sampled per range when splitting by seeks
+const SPLIT_SAMPLES: usize = 16;
 
-    pub fn get_value<T: FromMdbValue + 'cursor>(&'cursor self) -> T {
-        FromMdbValue::from_mdb_value(&self.value)
+/// SplitMix64 generator, small and good enough for sampling
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
     }
 
-    pub fn get<T: FromMdbValue + 'cursor, U: FromMdbValue + 'cursor>(&'cursor self) -> (T, U) {
-        (FromMdbValue::from_mdb_value(&self.key),
-         FromMdbValue::from_mdb_value(&self.value))
+    /// Uniform in `0..bound`, `bound` of 0 stands for 2^64
+    fn below(&mut self, bound: u64) -> u64 {
+        if bound == 0 {
+            return self.next_u64();
+        }
+        ((self.next_u64() as u128 * bound as u128) >> 64) as u64
     }
 }
 
-/// Allows the cration of custom cursor iteration behaviours.
-pub trait IterateCursor {
-    /// Returns true if initialization successful, for example that
-    /// the key exists.
-    fn init_cursor<'a, 'b: 'a>(&'a self, cursor: &mut Cursor<'b>) -> bool;
+/// Direction of `Database::page`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Direction {
+    Forward,
+    Backward,
+}
 
-    /// Returns true if there is still data and iterator is in correct range
-    fn move_to_next<'iter, 'cursor: 'iter>(&'iter self, cursor: &'cursor mut Cursor<'cursor>) -> bool;
+/// Opaque position `Database::page` resumes after, it can be sent
+/// around as bytes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PageToken(Vec<u8>);
 
-    /// Returns size hint considering current state of cursor
-    fn get_size_hint(&self, _cursor: &Cursor) -> (usize, Option<usize>) {
-        (0, None)
+impl PageToken {
+    /// Token to page from right after `key`
+    pub fn after_key<K: ToMdbValue>(key: &K) -> PageToken {
+        PageToken(encode_tuple(&[key.to_mdb_value().as_bytes()]))
     }
-}
 
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
 
-#[derive(Debug)]
-pub struct CursorIterator<'c, I> {
-    inner: I,
-    has_data: bool,
-    cursor: Cursor<'c>,
-    marker: ::std::marker::PhantomData<&'c ()>,
-}
-
-impl<'c, I: IterateCursor + 'c> CursorIterator<'c, I> {
-    fn wrap(cursor: Cursor<'c>, inner: I) -> CursorIterator<'c, I> {
-        let mut cursor = cursor;
-        let has_data = inner.init_cursor(&mut cursor);
-        CursorIterator {
-            inner,
-            has_data,
-            cursor,
-            marker: ::std::marker::PhantomData,
+    /// Restores token from `as_bytes`, `None` if they aren't a token
+    pub fn from_bytes(bytes: &[u8]) -> Option<PageToken> {
+        match decode_tuple(bytes)?.len() {
+            1 | 2 => Some(PageToken(bytes.to_vec())),
+            _ => None,
         }
     }
 
-    #[allow(dead_code)]
-    fn unwrap(self) -> Cursor<'c> {
-        self.cursor
-    }
-}
+    /// Positions cursor at the entry following the token in `direction`
+    fn resume<M: TxnMode>(&self, cursor: &mut Cursor<M>, direction: Direction) -> MdbResult<()> {
+        let elements = decode_tuple(&self.0).unwrap_or_default();
+        let (key, item) = match elements[..] {
+            [key] => (key, None),
+            [key, item] => (key, Some(item)),
+            _ => return Err(StateError("malformed page token".to_owned())),
+        };
 
-impl<'c, I: IterateCursor + 'c> Iterator for CursorIterator<'c, I> {
-    type Item = CursorValue<'c>;
+        // first entry not less than the token
+        let res = match item {
+            None => cursor.to_gte_key(&key),
+            Some(item) => match cursor.to_gte_item(&key, &item) {
+                // either no key or every item of it is less
+                Err(NotFound) => match cursor.to_gte_key(&key) {
+                    Ok(()) if cursor.cmp_key(&key.to_mdb_value())? == Ordering::Equal => cursor.to_next_key(),
+                    res => res,
+                },
+                res => res,
+            },
+        };
 
-    fn next(&mut self) -> Option<CursorValue<'c>> {
-        if !self.has_data {
-            None
-        } else {
-            match self.cursor.get_plain() {
-                Err(_) => None,
-                Ok((k, v)) => {
-                    self.has_data = unsafe { self.inner.move_to_next(mem::transmute::<&mut Cursor<'_>, &mut Cursor<'_>>(&mut self.cursor)) };
-                    Some(CursorValue {
-                        key: k,
-                        value: v,
-                        marker: ::std::marker::PhantomData
-                    })
+        match (direction, res) {
+            (Direction::Forward, Ok(())) => {
+                let at_token = cursor.cmp_key(&key.to_mdb_value())? == Ordering::Equal && match item {
+                    Some(item) => cursor.cmp_item(&item.to_mdb_value())? == Ordering::Equal,
+                    None => true,
+                };
+                match (at_token, item) {
+                    (false, _) => Ok(()),
+                    (true, Some(_)) => cursor.navigate(ffi::MDB_cursor_op::MDB_NEXT),
+                    (true, None) => cursor.to_next_key(),
                 }
-            }
+            },
+            (Direction::Backward, Ok(())) => cursor.navigate(ffi::MDB_cursor_op::MDB_PREV),
+            // every entry is less
+            (Direction::Backward, Err(NotFound)) => cursor.to_last(),
+            (_, res) => res,
         }
     }
+}
 
-    fn size_hint(&self) -> (usize, Option<usize>) {
-        self.inner.get_size_hint(&self.cursor)
-    }
+/// Entries returned by `Database::page`
+#[derive(Debug)]
+pub struct Page<'txn, M> {
+    pub entries: Vec<CursorValue<'txn, M>>,
+    /// Token for the next page, `None` if this one is the last
+    pub next: Option<PageToken>,
+}
+
+/// How `Join` combines keys of its cursors
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum JoinKind {
+    /// Keys present in every cursor
+    Inner,
+    /// Every key of the first cursor
+    Left,
+    /// Keys present in any cursor
+    Union,
+    /// Keys of the first cursor missing in all others
+    Difference,
 }
 
+/// Merge-join of several cursors of the same transaction, yields
+/// keys in sorted order with values of every cursor having them.
+/// Cursors skip ahead with `to_gte_key` instead of scanning, and
+/// keys are compared the way the first cursor's database does, so
+/// all databases are expected to order keys the same. In case of
+/// DbAllowDups the first item of a key is its value.
+///
+/// Errors of cursors are yielded, after which the join ends.
 #[derive(Debug)]
-pub struct CursorKeyRangeIter<'a> {
-    start_key: MdbValue<'a>,
-    end_key: MdbValue<'a>,
-    end_inclusive: bool,
-    marker: ::std::marker::PhantomData<&'a ()>,
+pub struct Join<'txn, M> {
+    kind: JoinKind,
+    cursors: Vec<Cursor<'txn, M>>,
+    heads: Vec<Option<(HeldValue<'txn>, HeldValue<'txn>)>>,
+    to_advance: Vec<usize>,
+    started: bool,
+    failed: bool,
 }
 
-impl<'a> CursorKeyRangeIter<'a> {
-    pub fn new<K: ToMdbValue+'a>(start_key: &'a K, end_key: &'a K, end_inclusive: bool) -> CursorKeyRangeIter<'a> {
-        CursorKeyRangeIter {
-            start_key: start_key.to_mdb_value(),
-            end_key: end_key.to_mdb_value(),
-            end_inclusive,
-            marker: ::std::marker::PhantomData,
+impl<'txn, M: TxnMode> Join<'txn, M> {
+    /// Fails if there are no cursors or they belong to different
+    /// transactions
+    pub fn new(kind: JoinKind, cursors: Vec<Cursor<'txn, M>>) -> MdbResult<Join<'txn, M>> {
+        let first = match cursors.first() {
+            Some(c) => c.txn,
+            None => return Err(StateError("join needs at least one cursor".to_owned())),
+        };
+        if cursors.iter().any(|c| !ptr::eq(c.txn, first)) {
+            return Err(StateError("joined cursors must belong to the same transaction".to_owned()));
         }
+        Ok(Join { kind, heads: cursors.iter().map(|_| None).collect(), cursors, to_advance: Vec::new(), started: false, failed: false })
     }
-}
 
-impl<'iter> IterateCursor for CursorKeyRangeIter<'iter> {
-    fn init_cursor<'a, 'b: 'a>(&'a self, cursor: & mut Cursor<'b>) -> bool {
-        let ok = unsafe {
-            cursor.to_gte_key(mem::transmute::<&'a MdbValue<'a>, &'b MdbValue<'b>>(&self.start_key)).is_ok()
-        };
-        ok && cursor.cmp_key(&self.end_key).is_less(self.end_inclusive)
+    pub fn inner(cursors: Vec<Cursor<'txn, M>>) -> MdbResult<Join<'txn, M>> {
+        Join::new(JoinKind::Inner, cursors)
     }
 
-    fn move_to_next<'i, 'c: 'i>(&'i self, cursor: &'c mut Cursor<'c>) -> bool {
-        let moved = cursor.to_next_key().is_ok();
-        if !moved {
-            false
-        } else {
-            cursor.cmp_key(&self.end_key).is_less(self.end_inclusive)
-        }
+    pub fn left(cursors: Vec<Cursor<'txn, M>>) -> MdbResult<Join<'txn, M>> {
+        Join::new(JoinKind::Left, cursors)
     }
-}
 
-#[derive(Debug)]
-pub struct CursorFromKeyIter<'a> {
-    start_key: MdbValue<'a>,
-    marker: ::std::marker::PhantomData<&'a ()>,
-}
+    pub fn union(cursors: Vec<Cursor<'txn, M>>) -> MdbResult<Join<'txn, M>> {
+        Join::new(JoinKind::Union, cursors)
+    }
 
+    pub fn difference(cursors: Vec<Cursor<'txn, M>>) -> MdbResult<Join<'txn, M>> {
+        Join::new(JoinKind::Difference, cursors)
+    }
 
-impl<'a> CursorFromKeyIter<'a> {
-    pub fn new<K: ToMdbValue+'a>(start_key: &'a K) -> CursorFromKeyIter<'a> {
-        CursorFromKeyIter {
-            start_key: start_key.to_mdb_value(),
-            marker: ::std::marker::PhantomData
-        }
+    fn cmp_keys(&self, a: &MdbValue, b: &MdbValue) -> Ordering {
+        let first = &self.cursors[0];
+        first.txn.cmp_keys(first.db, a, b)
     }
-}
 
-impl<'iter> IterateCursor for CursorFromKeyIter<'iter> {
-    fn init_cursor<'a, 'b: 'a>(&'a self, cursor: & mut Cursor<'b>) -> bool {
-        unsafe {
-            cursor.to_gte_key(mem::transmute::<&'a MdbValue<'a>, &'b MdbValue<'b>>(&self.start_key)).is_ok()
+    /// Updates head of cursor `i` after it moved
+    fn settle(&mut self, i: usize, res: MdbResult<()>) -> MdbResult<()> {
+        self.heads[i] = match res {
+            Ok(()) => {
+                let (key, value) = self.cursors[i].get_plain()?;
+                Some((HeldValue::new::<M>(key), HeldValue::new::<M>(value)))
+            },
+            Err(NotFound) => None,
+            Err(e) => return Err(e),
+        };
+        Ok(())
+    }
+
+    /// Moves cursor `i` to the first key >= `key` unless it is there already
+    fn seek(&mut self, i: usize, key: &MdbValue) -> MdbResult<()> {
+        match self.heads[i] {
+            Some((ref k, _)) if self.cmp_keys(&k.view(), key) == Ordering::Less => {
+                let res = self.cursors[i].to_gte_key(key);
+                self.settle(i, res)
+            },
+            // at the key or past it, or out of keys
+            _ => Ok(()),
         }
     }
 
-    fn move_to_next<'i, 'c: 'i>(&'i self, cursor: &'c mut Cursor<'c>) -> bool {
-        cursor.to_next_key().is_ok()
+    fn matches(&self, i: usize, key: &MdbValue) -> bool {
+        matches!(self.heads[i], Some((ref k, _)) if self.cmp_keys(&k.view(), key) == Ordering::Equal)
     }
-}
 
+    fn next_key(&mut self) -> MdbResult<Option<JoinValue<'txn, M>>> {
+        if !self.started {
+            self.started = true;
+            for i in 0..self.cursors.len() {
+                let res = self.cursors[i].to_first();
+                self.settle(i, res)?;
+            }
+        }
+        for i in std::mem::take(&mut self.to_advance) {
+            let res = self.cursors[i].to_next_key();
+            self.settle(i, res)?;
+        }
 
-#[derive(Debug)]
-pub struct CursorToKeyIter<'a> {
-    end_key: MdbValue<'a>,
-    marker: ::std::marker::PhantomData<&'a ()>,
-}
+        // keys are held apart from heads, which change under seeks
+        let key = loop {
+            let key = if self.kind == JoinKind::Union {
+                let mut min: Option<&HeldValue<'txn>> = None;
+                for (k, _) in self.heads.iter().flatten() {
+                    if min.map_or(true, |m| self.cmp_keys(&k.view(), &m.view()) == Ordering::Less) {
+                        min = Some(k);
+                    }
+                }
+                match min {
+                    Some(k) => break k.clone(),
+                    None => return Ok(None),
+                }
+            } else {
+                match self.heads[0] {
+                    Some((ref k, _)) => k.clone(),
+                    None => return Ok(None),
+                }
+            };
+            for i in 1..self.cursors.len() {
+                self.seek(i, &key.view())?;
+            }
 
+            match self.kind {
+                JoinKind::Inner => {
+                    // leapfrog to the greatest key among cursors
+                    let mut target = &key;
+                    for head in &self.heads[1..] {
+                        match head {
+                            Some((k, _)) => if self.cmp_keys(&k.view(), &target.view()) == Ordering::Greater { target = k },
+                            None => return Ok(None),
+                        }
+                    }
+                    if self.cmp_keys(&target.view(), &key.view()) == Ordering::Equal {
+                        break key;
+                    }
+                    let target = target.clone();
+                    self.seek(0, &target.view())?;
+                },
+                JoinKind::Difference => {
+                    if !(1..self.cursors.len()).any(|i| self.matches(i, &key.view())) {
+                        break key;
+                    }
+                    let res = self.cursors[0].to_next_key();
+                    self.settle(0, res)?;
+                },
+                JoinKind::Left | JoinKind::Union => break key,
+            }
+        };
 
-impl<'a> CursorToKeyIter<'a> {
-    pub fn new<K: ToMdbValue+'a>(end_key: &'a K) -> CursorToKeyIter<'a> {
-        CursorToKeyIter {
-            end_key: end_key.to_mdb_value(),
-            marker: ::std::marker::PhantomData,
-        }
+        let matching: Vec<bool> = (0..self.cursors.len()).map(|i| self.matches(i, &key.view())).collect();
+        self.to_advance = match self.kind {
+            JoinKind::Union => (0..self.cursors.len()).filter(|&i| matching[i]).collect(),
+            _ => vec![0],
+        };
+        let values = self.heads.iter().zip(matching)
+            .map(|(head, matched)| if matched { head.as_ref().map(|(_, v)| v.clone()) } else { None })
+            .collect();
+        Ok(Some(JoinValue { key, values, marker: ::std::marker::PhantomData }))
     }
 }
 
-impl<'iter> IterateCursor for CursorToKeyIter<'iter> {
-    fn init_cursor<'a, 'b: 'a>(&'a self, cursor: & mut Cursor<'b>) -> bool {
-        let ok = cursor.to_first().is_ok();
-        ok && cursor.cmp_key(&self.end_key).is_less(false)
-    }
+impl<'txn, M: TxnMode> Iterator for Join<'txn, M> {
+    type Item = MdbResult<JoinValue<'txn, M>>;
 
-    fn move_to_next<'i, 'c: 'i>(&'i self, cursor: &'c mut Cursor<'c>) -> bool {
-        let moved = cursor.to_next_key().is_ok();
-        if !moved {
-            false
-        } else {
-            cursor.cmp_key(&self.end_key).is_less(false)
+    fn next(&mut self) -> Option<MdbResult<JoinValue<'txn, M>>> {
+        if self.failed {
+            return None;
         }
+        let res = self.next_key().transpose();
+        self.failed = matches!(res, Some(Err(_)));
+        res
     }
 }
 
-#[allow(missing_copy_implementations)]
+/// Key yielded by `Join` with values of every cursor, `None`
+/// where a cursor doesn't have the key. In write transactions
+/// they are copied when yielded, as writes may change them.
 #[derive(Debug)]
-pub struct CursorIter;
-
+pub struct JoinValue<'txn, M> {
+    key: HeldValue<'txn>,
+    values: Vec<Option<HeldValue<'txn>>>,
+    marker: ::std::marker::PhantomData<M>,
+}
 
-impl IterateCursor for CursorIter {
-    fn init_cursor<'a, 'b: 'a>(&'a self, cursor: & mut Cursor<'b>) -> bool {
-        cursor.to_first().is_ok()
+impl<'txn, M: TxnMode> JoinValue<'txn, M> {
+    pub fn get_key<T: FromTxnValue<'txn, M>>(&self) -> T {
+        self.key.decode()
     }
 
-    fn move_to_next<'i, 'c: 'i>(&'i self, cursor: &'c mut Cursor<'c>) -> bool {
-        cursor.to_next_key().is_ok()
+    /// Value of the cursor at `index` in the join
+    pub fn get_value<T: FromTxnValue<'txn, M>>(&self, index: usize) -> Option<T> {
+        self.values.get(index)?.as_ref().map(HeldValue::decode)
     }
 }
 
 
 #[derive(Debug)]
-pub struct CursorItemIter<'a> {
+pub struct CursorItemRangeIter<'a> {
     key: MdbValue<'a>,
-    marker: ::std::marker::PhantomData<&'a ()>,
+    start: Bound<Vec<u8>>,
+    end: Bound<Vec<u8>>,
+    reverse: bool,
 }
 
+impl<'a> CursorItemRangeIter<'a> {
+    /// Positions cursor at the first item within range from the
+    /// iteration side
+    fn seek<M: TxnMode>(&self, cursor: &mut Cursor<M>) -> MdbResult<()> {
+        if !self.reverse {
+            return match self.start {
+                Bound::Unbounded => cursor.to_key(&self.key),
+                Bound::Included(ref start) => cursor.to_gte_item(&self.key, start),
+                Bound::Excluded(ref start) => {
+                    cursor.to_gte_item(&self.key, start)?;
+                    if cursor.cmp_item(&start.to_mdb_value())? == Ordering::Equal {
+                        cursor.to_next_item()
+                    } else {
+                        Ok(())
+                    }
+                },
+            };
+        }
 
-impl<'a> CursorItemIter<'a> {
-    pub fn new<K: ToMdbValue+'a>(key: &'a K) -> CursorItemIter<'a> {
-        CursorItemIter {
-            key: key.to_mdb_value(),
-            marker: ::std::marker::PhantomData
+        let (end, inclusive) = match self.end {
+            Bound::Unbounded => {
+                cursor.to_key(&self.key)?;
+                return cursor.to_last_item();
+            },
+            Bound::Included(ref end) => (end.to_mdb_value(), true),
+            Bound::Excluded(ref end) => (end.to_mdb_value(), false),
+        };
+        match cursor.to_gte_item(&self.key, &end) {
+            Ok(()) => match cursor.cmp_item(&end)? {
+                Ordering::Equal if inclusive => Ok(()),
+                _ => cursor.to_prev_item(),
+            },
+            // either no key or every item is less
+            Err(NotFound) => {
+                cursor.to_key(&self.key)?;
+                cursor.to_last_item()
+            },
+            Err(e) => Err(e),
         }
     }
-}
 
-impl<'iter> IterateCursor for CursorItemIter<'iter> {
-    fn init_cursor<'a, 'b: 'a>(&'a self, cursor: & mut Cursor<'b>) -> bool {
-        unsafe {
-            cursor.to_key(mem::transmute::<&MdbValue, &'b MdbValue<'b>>(&self.key)).is_ok()
-        }
+    /// Checks the far bound of the iteration
+    fn in_range<M: TxnMode>(&self, cursor: &mut Cursor<M>) -> MdbResult<bool> {
+        let (bound, past) = if self.reverse {
+            (&self.start, Ordering::Less)
+        } else {
+            (&self.end, Ordering::Greater)
+        };
+        Ok(match *bound {
+            Bound::Unbounded => true,
+            Bound::Included(ref v) => cursor.cmp_item(&v.to_mdb_value())? != past,
+            Bound::Excluded(ref v) => cursor.cmp_item(&v.to_mdb_value())? == past.reverse(),
+        })
     }
+}
 
-    fn move_to_next<'i, 'c: 'i>(&'i self, cursor: &'c mut Cursor<'c>) -> bool {
-        cursor.to_next_item().is_ok()
+impl<'iter> IterateCursor for CursorItemRangeIter<'iter> {
+    fn init_cursor<M: TxnMode>(&self, cursor: &mut Cursor<M>) -> bool {
+        self.seek(cursor).is_ok() && self.in_range(cursor).unwrap_or(false)
     }
 
-    fn get_size_hint(&self, c: &Cursor) -> (usize, Option<usize>) {
-        match c.item_count() {
-            Err(_) => (0, None),
-            Ok(cnt) => (0, Some(cnt))
-        }
+    fn move_to_next<M: TxnMode>(&self, cursor: &mut Cursor<M>) -> bool {
+        let moved = if self.reverse { cursor.to_prev_item() } else { cursor.to_next_item() };
+        moved.is_ok() && self.in_range(cursor).unwrap_or(false)
     }
 }
 
@@ -1832,6 +4792,9 @@ impl<'iter> IterateCursor for CursorItemIter<'iter> {
 #[derive(Copy, Clone, Debug)]
 pub struct MdbValue<'a> {
     value: MDB_val,
+    // bytes of a value encoded on the fly, `value` can't point to
+    // them as MdbValue is moved around, see `raw`
+    inline: Option<[u8; 8]>,
     marker: ::std::marker::PhantomData<&'a ()>,
 }
 
@@ -1846,10 +4809,29 @@ impl<'a> MdbValue<'a> {
                 mv_data: data,
                 mv_size: len as size_t
             },
+            inline: None,
+            marker: ::std::marker::PhantomData
+        }
+    }
+
+    /// Value holding its own bytes
+    pub(crate) fn new_inline(bytes: [u8; 8]) -> MdbValue<'a> {
+        MdbValue {
+            value: MDB_val { mv_data: ptr::null(), mv_size: bytes.len() },
+            inline: Some(bytes),
             marker: ::std::marker::PhantomData
         }
     }
 
+    /// MDB_val to hand to LMDB, valid as long as the value stays in place
+    #[inline]
+    pub(crate) fn raw(&self) -> MDB_val {
+        match self.inline {
+            Some(ref bytes) => MDB_val { mv_data: bytes.as_ptr() as *const c_void, mv_size: bytes.len() },
+            None => self.value,
+        }
+    }
+
 
     /// # Safety
     ///  
@@ -1878,8 +4860,23 @@ impl<'a> MdbValue<'a> {
     ///  
     /// caller is responsible for safety
     #[inline]
-    pub unsafe fn get_ref(&'a self) -> *const c_void {
-        self.value.mv_data
+    pub unsafe fn get_ref(&self) -> *const c_void {
+        self.raw().mv_data
+    }
+
+    /// Bytes of the value
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe { as_slice(&self.raw()) }
+    }
+
+    /// Bytes the value points to, valid for the whole `'a`. Values
+    /// holding their own bytes are only ever written, never read.
+    #[inline]
+    pub(crate) fn data(&self) -> &'a [u8] {
+        assert!(self.inline.is_none(), "value holds its own bytes");
+        // constructing MdbValue<'a> promises the data lives for 'a
+        unsafe { as_slice(&self.value) }
     }
 
     #[inline]
@@ -1,5 +1,7 @@
 #![allow(trivial_casts)]
 #![allow(trivial_numeric_casts)]
+#![allow(unknown_lints, mismatched_lifetime_syntaxes)]
+#![allow(clippy::needless_lifetimes, clippy::empty_line_after_doc_comments)]
 
 extern crate libc;
 
@@ -11,14 +13,20 @@ extern crate liblmdb_sys as ffi;
 pub use libc::c_int;
 pub use ffi::{mdb_filehandle_t, MDB_stat, MDB_envinfo, MDB_val};
 pub use core::{EnvBuilder, Environment, EnvFlags, EnvCreateFlags};
-pub use core::{Database, DbFlags, DbHandle};
+pub use core::{ReaderInfo, LaggingReader, ReopenPolicy};
+pub use core::{Database, DbFlags, DbHandle, DbInfo, WriteFlags};
 pub use core::{Transaction, ReadonlyTransaction, MdbError, MdbValue};
-pub use core::{Cursor, CursorValue, CursorIter, CursorKeyRangeIter};
-pub use traits::{FromMdbValue, ToMdbValue};
+pub use core::{Cursor, CursorValue, CursorIter, CursorKeyRangeIter, CursorIterMut, EntryMut};
+pub use core::{Join, JoinKind, JoinValue, GroupIter, Group, GroupItems};
+pub use core::{Direction, Page, PageToken, KeyRange};
+pub use traits::{FromMdbValue, ToMdbValue, FromTxnValue, TxnMode, ReadOnly, ReadWrite};
 
+pub mod comparators;
 pub mod core;
 pub mod traits;
 mod utils;
 
 #[cfg(test)]
+#[allow(clippy::needless_borrows_for_generic_args, clippy::redundant_static_lifetimes)]
+#[allow(clippy::manual_memcpy, clippy::ptr_arg, clippy::unnecessary_cast)]
 mod tests;
@@ -1,4 +1,6 @@
 use std::{env, fs};
+use std::collections::HashSet;
+use std::ops::Bound;
 use std::path::PathBuf;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Once;
@@ -8,7 +10,7 @@ use libc::c_int;
 
 use self::core::*;
 use ffi::MDB_val;
-use crate::traits::FromMdbValue;
+use crate::traits::{FromMdbValue, ToMdbValue};
 use crate::*;
 
 const USER_DIR: u32 = 0o777;
@@ -74,7 +76,7 @@ fn test_environment() {
 
     db.set(&key, &value).unwrap();
 
-    let v = db.get::<&str>(&key).unwrap();
+    let v = db.get::<String>(&key).unwrap();
     assert!(v == value, "Written {} and read {}", &value, &v);
 }
 
@@ -96,11 +98,11 @@ fn test_single_values() {
     assert!(db.get::<()>(&test_key1).is_err(), "Key shouldn't exist yet");
 
     assert!(db.set(&test_key1, &test_data1).is_ok());
-    let v = db.get::<&str>(&test_key1).unwrap();
+    let v = db.get::<String>(&test_key1).unwrap();
     assert!(v == test_data1, "Data written differs from data read");
 
     assert!(db.set(&test_key1, &test_data2).is_ok());
-    let v = db.get::<&str>(&test_key1).unwrap();
+    let v = db.get::<String>(&test_key1).unwrap();
     assert!(v == test_data2, "Data written differs from data read");
 
     assert!(db.del(&test_key1).is_ok());
@@ -125,16 +127,16 @@ fn test_multiple_values() {
     assert!(db.get::<()>(&test_key1).is_err(), "Key shouldn't exist yet");
 
     assert!(db.set(&test_key1, &test_data1).is_ok());
-    let v = db.get::<&str>(&test_key1).unwrap();
+    let v = db.get::<String>(&test_key1).unwrap();
     assert!(v == test_data1, "Data written differs from data read");
 
     assert!(db.set(&test_key1, &test_data2).is_ok());
-    let v = db.get::<&str>(&test_key1).unwrap();
+    let v = db.get::<String>(&test_key1).unwrap();
     assert!(v == test_data1, "It should still return first value");
 
     assert!(db.del_item(&test_key1, &test_data1).is_ok());
 
-    let v = db.get::<&str>(&test_key1).unwrap();
+    let v = db.get::<String>(&test_key1).unwrap();
     assert!(v == test_data2, "It should return second value");
     assert!(db.del(&test_key1).is_ok());
 
@@ -157,16 +159,16 @@ fn test_append_duplicate() {
     let test_data2 = "value2";
 
     assert!(db.append(&test_key1, &test_data1).is_ok());
-    let v = db.get::<&str>(&test_key1).unwrap();
+    let v = db.get::<String>(&test_key1).unwrap();
     assert!(v == test_data1, "Data written differs from data read");
 
     assert!(db.append_duplicate(&test_key1, &test_data2).is_ok());
-    let v = db.get::<&str>(&test_key1).unwrap();
+    let v = db.get::<String>(&test_key1).unwrap();
     assert!(v == test_data1, "It should still return first value");
 
     assert!(db.del_item(&test_key1, &test_data1).is_ok());
 
-    let v = db.get::<&str>(&test_key1).unwrap();
+    let v = db.get::<String>(&test_key1).unwrap();
     assert!(v == test_data2, "It should return second value");
 
     match db.append_duplicate(&test_key1, &test_data1).err().unwrap() {
@@ -193,7 +195,7 @@ fn test_insert_values() {
     assert!(db.get::<()>(&test_key1).is_err(), "Key shouldn't exist yet");
 
     assert!(db.set(&test_key1, &test_data1).is_ok());
-    let v = db.get::<&str>(&test_key1).unwrap();
+    let v = db.get::<String>(&test_key1).unwrap();
     assert!(v == test_data1, "Data written differs from data read");
 
     assert!(db.insert(&test_key1, &test_data2).is_err(), "Inserting should fail if key exists");
@@ -327,7 +329,7 @@ fn test_cursors() {
 
     assert!(cursor.replace(&new_value).is_ok());
     {
-        let (_, v) = cursor.get::<(), &str>().unwrap();
+        let (_, v) = cursor.get::<(), String>().unwrap();
         // NOTE: this asserting will work once new_value is
         // of the same length as it is inplace change
         assert!(v == new_value);
@@ -1020,3 +1022,2182 @@ fn test_conversion_to_string() {
     }
     tx.abort();
 }
+
+#[test]
+fn test_reader_list() {
+    let env = EnvBuilder::new().open(next_path(), USER_DIR).unwrap();
+    assert!(env.reader_list().unwrap().is_empty());
+
+    let reader = env.get_reader().unwrap();
+    let readers = env.reader_list().unwrap();
+    assert_eq!(readers.len(), 1);
+    assert!(readers[0].txnid.is_some());
+    drop(reader);
+}
+
+#[test]
+fn test_reader_maintenance() {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    let reported = Arc::new(Mutex::new(Vec::new()));
+    let sink = reported.clone();
+    let env = EnvBuilder::new()
+        .reader_maintenance(Duration::from_millis(10), Duration::from_millis(30))
+        .on_lagging_reader(move |r| sink.lock().unwrap().push(r.clone()))
+        .open(next_path(), USER_DIR)
+        .unwrap();
+    let db = env.get_default_db(DbFlags::empty()).unwrap();
+
+    let reader = env.get_reader().unwrap();
+    for i in 0..3u32 {
+        let txn = env.new_transaction().unwrap();
+        txn.bind(&db).set(&i.to_be_bytes().to_vec(), &"value").unwrap();
+        txn.commit().unwrap();
+    }
+
+    let mut waited = 0;
+    while reported.lock().unwrap().is_empty() && waited < 100 {
+        thread::sleep(Duration::from_millis(10));
+        waited += 1;
+    }
+    drop(reader);
+    env.stop_reader_maintenance();
+
+    let reported = reported.lock().unwrap();
+    assert_eq!(reported.len(), 1, "lagging reader should be reported exactly once");
+    assert_eq!(reported[0].lag, 3);
+    assert!(reported[0].age >= Duration::from_millis(30));
+}
+
+#[test]
+fn test_env_is_send_sync() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Environment>();
+}
+
+#[test]
+fn test_concurrent_env_clone_drop() {
+    let env = EnvBuilder::new()
+        .max_dbs(5)
+        .open(next_path(), USER_DIR)
+        .unwrap();
+    let db = env.create_db("shared", DbFlags::empty()).unwrap();
+
+    let workers: Vec<_> = (0..16u32).map(|i| {
+        let env = env.clone();
+        thread::spawn(move || {
+            for j in 0..50u32 {
+                let local = env.clone();
+                let shared = local.clone();
+                drop(local);
+                if j % 10 == 0 {
+                    let txn = shared.new_transaction().unwrap();
+                    txn.bind(&db).set(&format!("{}-{}", i, j), &"v").unwrap();
+                    txn.commit().unwrap();
+                } else {
+                    let reader = shared.get_reader().unwrap();
+                    let _ = reader.bind(&db).get::<()>(&"missing");
+                }
+            }
+        })
+    }).collect();
+
+    for worker in workers {
+        worker.join().unwrap();
+    }
+
+    {
+        let reader = env.get_reader().unwrap();
+        let db = reader.bind(&db);
+        assert_eq!(db.stat().unwrap().ms_entries, 16 * 5);
+    }
+    assert!(env.close().is_ok());
+}
+
+#[test]
+fn test_env_close_in_use() {
+    let env = EnvBuilder::new().open(next_path(), USER_DIR).unwrap();
+    let other = env.clone();
+    {
+        let reader = other.get_reader().unwrap();
+        let db = other.get_default_db(DbFlags::empty()).unwrap();
+        let db = reader.bind(&db);
+        let _cursor = db.new_cursor().unwrap();
+
+        match env.clone().close() {
+            Err(EnvInUse { environments, transactions, cursors }) => {
+                assert_eq!(environments, 2);
+                assert_eq!(transactions, 1);
+                assert_eq!(cursors, 1);
+            },
+            _ => panic!("expected EnvInUse"),
+        }
+    }
+    drop(env);
+    assert!(other.close().is_ok());
+}
+
+#[test]
+fn test_reopen_reuses_env() {
+    let path = next_path();
+    let env = EnvBuilder::new().open(&path, USER_DIR).unwrap();
+    let db = env.get_default_db(DbFlags::empty()).unwrap();
+    {
+        let txn = env.new_transaction().unwrap();
+        txn.bind(&db).set(&"key", &"value").unwrap();
+        txn.commit().unwrap();
+    }
+
+    // same directory through a different spelling of its path
+    let alias = path.join("..").join(path.file_name().unwrap());
+    let reopened = EnvBuilder::new().open(&alias, USER_DIR).unwrap();
+    {
+        let reader = reopened.get_reader().unwrap();
+        let value: String = reader.bind(&db).get(&"key").unwrap();
+        assert_eq!(value, "value");
+    }
+
+    match env.close() {
+        Err(EnvInUse { environments: 1, .. }) => (),
+        _ => panic!("expected the reopened environment to share the handle"),
+    }
+    assert!(reopened.close().is_ok());
+
+    // once closed it can be opened again
+    assert!(EnvBuilder::new().reopen_policy(ReopenPolicy::Fail).open(&path, USER_DIR).is_ok());
+}
+
+#[test]
+fn test_reopen_fails() {
+    let path = next_path();
+    let env = EnvBuilder::new().open(&path, USER_DIR).unwrap();
+    match EnvBuilder::new().reopen_policy(ReopenPolicy::Fail).open(&path, USER_DIR) {
+        Err(AlreadyOpen) => (),
+        _ => panic!("expected AlreadyOpen"),
+    }
+    drop(env);
+}
+
+#[test]
+fn test_reopen_with_different_settings() {
+    use std::time::Duration;
+
+    let path = next_path();
+    let builder = || EnvBuilder::new().max_dbs(5).map_size(0x100000);
+    let env = builder().open(&path, USER_DIR).unwrap();
+
+    assert!(builder().open(&path, USER_DIR).is_ok());
+    // settings left out and flags changeable after opening don't matter
+    assert!(EnvBuilder::new().open(&path, USER_DIR).is_ok());
+    assert!(builder().flags(EnvCreateFlags::EnvCreateNoMetaSync).open(&path, USER_DIR).is_ok());
+    assert!(builder().map_size(0x10000).open(&path, USER_DIR).is_ok());
+
+    let mismatch = |builder: EnvBuilder| match builder.open(&path, USER_DIR) {
+        Err(SettingsMismatch { setting }) => setting,
+        _ => panic!("expected SettingsMismatch"),
+    };
+    assert_eq!(mismatch(builder().max_dbs(6)), "max_dbs");
+    assert_eq!(mismatch(builder().map_size(0x200000)), "map_size");
+    assert_eq!(mismatch(builder().flags(EnvCreateFlags::EnvCreateNoTls)), "flags");
+    assert_eq!(mismatch(builder().compare_with("", |a, b| b.cmp(a))), "comparators");
+    let maintenance = builder().reader_maintenance(Duration::from_secs(1), Duration::from_secs(1));
+    assert_eq!(mismatch(maintenance), "reader_maintenance");
+    drop(env);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_reopen_nosubdir_by_inode() {
+    let dir = next_path();
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("data.mdb");
+    let env = EnvBuilder::new()
+        .flags(EnvCreateFlags::EnvCreateNoSubDir)
+        .open(&path, USER_DIR)
+        .unwrap();
+
+    let link = dir.join("link.mdb");
+    fs::hard_link(&path, &link).unwrap();
+    let res = EnvBuilder::new()
+        .flags(EnvCreateFlags::EnvCreateNoSubDir)
+        .reopen_policy(ReopenPolicy::Fail)
+        .open(&link, USER_DIR);
+    match res {
+        Err(AlreadyOpen) => (),
+        _ => panic!("expected AlreadyOpen for a hard link to open data file"),
+    }
+    drop(env);
+}
+
+#[test]
+fn test_concurrent_open_same_path() {
+    let path = next_path();
+    fs::create_dir_all(&path).unwrap();
+    let workers: Vec<_> = (0..8).map(|_| {
+        let path = path.clone();
+        thread::spawn(move || {
+            for _ in 0..20 {
+                let env = EnvBuilder::new().open(&path, USER_DIR).unwrap();
+                let db = env.get_default_db(DbFlags::empty()).unwrap();
+                let txn = env.new_transaction().unwrap();
+                txn.bind(&db).set(&"key", &"value").unwrap();
+                txn.commit().unwrap();
+            }
+        })
+    }).collect();
+    for worker in workers {
+        worker.join().unwrap();
+    }
+}
+
+#[test]
+fn test_concurrent_first_open_nosubdir() {
+    use std::sync::{Arc, Barrier};
+
+    let dir = next_path();
+    fs::create_dir_all(&dir).unwrap();
+    for round in 0..50 {
+        let path = dir.join(format!("data-{}.mdb", round));
+        let barrier = Arc::new(Barrier::new(8));
+        let workers: Vec<_> = (0..8).map(|_| {
+            let path = path.clone();
+            let barrier = barrier.clone();
+            thread::spawn(move || {
+                barrier.wait();
+                EnvBuilder::new()
+                    .flags(EnvCreateFlags::EnvCreateNoSubDir)
+                    .reopen_policy(ReopenPolicy::Fail)
+                    .open(&path, USER_DIR)
+            })
+        }).collect();
+        // environments are kept alive until all opens are done
+        let results: Vec<_> = workers.into_iter().map(|w| w.join().unwrap()).collect();
+        assert_eq!(results.iter().filter(|res| res.is_ok()).count(), 1);
+        assert!(results.iter().all(|res| matches!(res, Ok(_) | Err(AlreadyOpen))));
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_non_utf8_path() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let root = next_path();
+    let path = root.join(OsStr::from_bytes(b"env-\xff\xfe"));
+    let env = EnvBuilder::new().open(&path, USER_DIR).unwrap();
+    let db = env.get_default_db(DbFlags::empty()).unwrap();
+    {
+        let txn = env.new_transaction().unwrap();
+        txn.bind(&db).set(&"key", &"value").unwrap();
+        txn.commit().unwrap();
+    }
+    assert!(path.join("data.mdb").is_file());
+
+    let backup = root.join(OsStr::from_bytes(b"backup-\xff"));
+    fs::create_dir_all(&backup).unwrap();
+    env.copy_to_path(&backup).unwrap();
+    assert!(backup.join("data.mdb").is_file());
+}
+
+#[test]
+fn test_nosubdir_creates_parent() {
+    let path = next_path().join("nested").join("data.mdb");
+    let env = EnvBuilder::new()
+        .flags(EnvCreateFlags::EnvCreateNoSubDir)
+        .open(&path, USER_DIR)
+        .unwrap();
+    assert!(path.is_file());
+    drop(env);
+}
+
+#[test]
+fn test_nosubdir_parent_missing() {
+    let parent = next_path().join("nested");
+    let res = EnvBuilder::new()
+        .flags(EnvCreateFlags::EnvCreateNoSubDir)
+        .autocreate_dir(false)
+        .open(parent.join("data.mdb"), USER_DIR);
+    match res {
+        Err(DirMissing(dir)) => assert_eq!(dir, parent),
+        _ => panic!("expected DirMissing"),
+    }
+}
+
+#[test]
+fn test_env_dir_missing() {
+    let dir = next_path().join("nested");
+    let res = EnvBuilder::new()
+        .autocreate_dir(false)
+        .open(&dir, USER_DIR);
+    match res {
+        Err(DirMissing(path)) => assert_eq!(path, dir),
+        _ => panic!("expected DirMissing"),
+    }
+}
+
+#[test]
+fn test_path_is_file() {
+    let dir = next_path();
+    fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("file");
+    fs::write(&file, b"not a directory").unwrap();
+
+    match EnvBuilder::new().open(&file, USER_DIR) {
+        Err(PathIsFile(path)) => assert_eq!(path, file),
+        _ => panic!("expected PathIsFile"),
+    }
+
+    let res = EnvBuilder::new()
+        .flags(EnvCreateFlags::EnvCreateNoSubDir)
+        .open(file.join("data.mdb"), USER_DIR);
+    match res {
+        Err(PathIsFile(path)) => assert_eq!(path, file),
+        _ => panic!("expected PathIsFile for parent of data file"),
+    }
+}
+
+#[test]
+fn test_path_is_dir() {
+    let dir = next_path();
+    fs::create_dir_all(&dir).unwrap();
+
+    let res = EnvBuilder::new()
+        .flags(EnvCreateFlags::EnvCreateNoSubDir)
+        .open(&dir, USER_DIR);
+    match res {
+        Err(PathIsDir(path)) => assert_eq!(path, dir),
+        _ => panic!("expected PathIsDir for data file"),
+    }
+}
+
+#[cfg(unix)]
+#[test]
+#[ignore = "permissions aren't enforced for root, run with --ignored as a regular user"]
+fn test_path_permission_denied() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = next_path();
+    fs::create_dir_all(&dir).unwrap();
+    fs::set_permissions(&dir, fs::Permissions::from_mode(0o500)).unwrap();
+
+    let res = EnvBuilder::new().open(dir.join("env"), USER_DIR);
+    fs::set_permissions(&dir, fs::Permissions::from_mode(0o700)).unwrap();
+    match res {
+        Err(PermissionDenied(path)) => assert_eq!(path, dir.join("env")),
+        _ => panic!("expected PermissionDenied"),
+    }
+}
+
+const RESIZE_PATH_VAR: &str = "LMDB_RS_RESIZE_PATH";
+
+/// Run in a separate process by `test_map_resized_by_other_process`
+#[test]
+#[ignore]
+fn grow_map_in_child_process() {
+    let path = match env::var_os(RESIZE_PATH_VAR) {
+        Some(path) => path,
+        None => return,
+    };
+    let env = EnvBuilder::new()
+        .map_size(0x1000000)
+        .open(path, USER_DIR)
+        .unwrap();
+    let db = env.get_default_db(DbFlags::empty()).unwrap();
+    let txn = env.new_transaction().unwrap();
+    {
+        let db = txn.bind(&db);
+        let data = vec![0x5Au8; 0x1000];
+        for i in 0..512u32 {
+            db.set(&i.to_be_bytes().to_vec(), &data).unwrap();
+        }
+    }
+    txn.commit().unwrap();
+}
+
+#[test]
+fn test_map_resized_by_other_process() {
+    use std::process::Command;
+
+    let path = next_path();
+    let env = EnvBuilder::new()
+        .map_size(0x100000)
+        .open(&path, USER_DIR)
+        .unwrap();
+    let db = env.get_default_db(DbFlags::empty()).unwrap();
+    {
+        let txn = env.new_transaction().unwrap();
+        txn.bind(&db).set(&"key", &"value").unwrap();
+        txn.commit().unwrap();
+    }
+
+    let status = Command::new(env::current_exe().unwrap())
+        .args(["--ignored", "--exact", "tests::grow_map_in_child_process"])
+        .env(RESIZE_PATH_VAR, &path)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    assert_eq!(env.map_resize_count(), 0);
+
+    {
+        let reader = env.get_reader().unwrap();
+        let db = reader.bind(&db);
+        assert_eq!(db.stat().unwrap().ms_entries, 513);
+    }
+    assert_eq!(env.map_resize_count(), 1);
+    assert!(env.info().unwrap().me_mapsize >= 0x1000000);
+
+    // further transactions don't need to resize again
+    let txn = env.new_transaction().unwrap();
+    txn.bind(&db).set(&"key", &"other").unwrap();
+    txn.commit().unwrap();
+    assert_eq!(env.map_resize_count(), 1);
+}
+
+#[test]
+fn test_map_resized_under_concurrent_readers() {
+    use std::process::Command;
+    use std::sync::{Arc, Barrier};
+
+    let path = next_path();
+    let env = EnvBuilder::new()
+        .map_size(0x100000)
+        .open(&path, USER_DIR)
+        .unwrap();
+    let db = env.get_default_db(DbFlags::empty()).unwrap();
+    {
+        let txn = env.new_transaction().unwrap();
+        txn.bind(&db).set(&"key", &"value").unwrap();
+        txn.commit().unwrap();
+    }
+
+    let status = Command::new(env::current_exe().unwrap())
+        .args(["--ignored", "--exact", "tests::grow_map_in_child_process"])
+        .env(RESIZE_PATH_VAR, &path)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    // the first readers race to adopt new size, the rest either
+    // retry after MapResized or see it adopted
+    let barrier = Arc::new(Barrier::new(8));
+    let threads: Vec<_> = (0..8).map(|_| {
+        let env = env.clone();
+        let barrier = barrier.clone();
+        thread::spawn(move || {
+            barrier.wait();
+            for _ in 0..50 {
+                let reader = loop {
+                    match env.get_reader() {
+                        Ok(reader) => break reader,
+                        Err(MapResized) => thread::yield_now(),
+                        Err(e) => panic!("unexpected error {}", e),
+                    }
+                };
+                assert_eq!(reader.bind(&db).stat().unwrap().ms_entries, 513);
+            }
+        })
+    }).collect();
+    for t in threads {
+        t.join().unwrap();
+    }
+    assert!(env.map_resize_count() >= 1);
+}
+
+#[test]
+fn test_create_db_in_txn() {
+    let env = EnvBuilder::new()
+        .max_dbs(5)
+        .open(next_path(), USER_DIR)
+        .unwrap();
+
+    // aborted transaction leaves nothing behind
+    {
+        let txn = env.new_transaction().unwrap();
+        let db = txn.create_db("tenant", DbFlags::empty()).unwrap();
+        txn.bind(&db).set(&"key", &"value").unwrap();
+        txn.abort();
+    }
+    match env.get_db("tenant", DbFlags::empty()) {
+        Err(NotFound) => (),
+        _ => panic!("db shouldn't exist after abort"),
+    }
+
+    // committed one creates db with its first data atomically
+    {
+        let txn = env.new_transaction().unwrap();
+        let db = txn.create_db("tenant", DbFlags::empty()).unwrap();
+        txn.bind(&db).set(&"key", &"value").unwrap();
+        txn.commit().unwrap();
+    }
+    let db = env.get_db("tenant", DbFlags::empty()).unwrap();
+    let reader = env.get_reader().unwrap();
+    let value: String = reader.bind(&db).get(&"key").unwrap();
+    assert_eq!(value, "value");
+}
+
+#[test]
+fn test_open_db_in_txn() {
+    let env = EnvBuilder::new()
+        .max_dbs(5)
+        .open(next_path(), USER_DIR)
+        .unwrap();
+
+    let txn = env.new_transaction().unwrap();
+    match txn.open_db("missing", DbFlags::empty()) {
+        Err(NotFound) => (),
+        _ => panic!("expected NotFound"),
+    }
+    let created = txn.create_db("existing", DbFlags::empty()).unwrap();
+    let opened = txn.open_db("existing", DbFlags::empty()).unwrap();
+    txn.bind(&created).set(&"key", &"value").unwrap();
+    let value: String = txn.bind(&opened).get(&"key").unwrap();
+    assert_eq!(value, "value");
+    txn.commit().unwrap();
+}
+
+#[test]
+fn test_create_db_in_child_txn() {
+    let env = EnvBuilder::new()
+        .max_dbs(5)
+        .open(next_path(), USER_DIR)
+        .unwrap();
+
+    let txn = env.new_transaction().unwrap();
+    {
+        let child = txn.new_child().unwrap();
+        let db = child.create_db("nested", DbFlags::empty()).unwrap();
+        child.bind(&db).set(&"key", &"value").unwrap();
+        child.commit().unwrap();
+    }
+    txn.commit().unwrap();
+
+    let db = env.get_db("nested", DbFlags::empty()).unwrap();
+    let reader = env.get_reader().unwrap();
+    let value: String = reader.bind(&db).get(&"key").unwrap();
+    assert_eq!(value, "value");
+}
+
+#[test]
+fn test_db_flags_mismatch() {
+    let env = EnvBuilder::new()
+        .max_dbs(5)
+        .open(next_path(), USER_DIR)
+        .unwrap();
+
+    let db = env.create_db("dups", DbFlags::DbAllowDups).unwrap();
+    assert_eq!(db.flags(), DbFlags::DbAllowDups);
+
+    // same flags hit the cache
+    let db = env.get_db("dups", DbFlags::DbAllowDups | DbFlags::DbCreate).unwrap();
+    assert_eq!(db.flags(), DbFlags::DbAllowDups);
+
+    match env.get_db("dups", DbFlags::empty()) {
+        Err(FlagsMismatch { requested, actual }) => {
+            assert_eq!(requested, DbFlags::empty());
+            assert_eq!(actual, DbFlags::DbAllowDups);
+        }
+        other => panic!("expected flags mismatch, got {:?}", other),
+    }
+
+    // not cached yet, checked against stored flags
+    let path = next_path();
+    {
+        let env = EnvBuilder::new().max_dbs(5).open(&path, USER_DIR).unwrap();
+        env.create_db("ints", DbFlags::DbIntKey).unwrap();
+        env.close().unwrap();
+    }
+    let env = EnvBuilder::new().max_dbs(5).open(&path, USER_DIR).unwrap();
+    assert!(matches!(env.get_db("ints", DbFlags::DbReverseKey), Err(FlagsMismatch { .. })));
+    assert_eq!(env.get_db("ints", DbFlags::DbIntKey).unwrap().flags(), DbFlags::DbIntKey);
+
+    let txn = env.new_transaction().unwrap();
+    assert!(matches!(txn.open_db("ints", DbFlags::empty()), Err(FlagsMismatch { .. })));
+}
+
+#[test]
+fn test_main_db_flags_mismatch() {
+    let env = EnvBuilder::new().open(next_path(), USER_DIR).unwrap();
+
+    // empty main db can still be given flags
+    let db = env.get_default_db(DbFlags::DbAllowDups).unwrap();
+    assert_eq!(db.flags(), DbFlags::DbAllowDups);
+    assert!(matches!(env.get_default_db(DbFlags::empty()), Err(FlagsMismatch { .. })));
+
+    let env = EnvBuilder::new().open(next_path(), USER_DIR).unwrap();
+    let db = env.get_default_db(DbFlags::empty()).unwrap();
+    {
+        let txn = env.new_transaction().unwrap();
+        txn.bind(&db).set(&"key", &"value").unwrap();
+        txn.commit().unwrap();
+    }
+    assert!(matches!(env.get_default_db(DbFlags::DbAllowDups), Err(FlagsMismatch { .. })));
+}
+
+#[test]
+fn test_list_dbs() {
+    let env = EnvBuilder::new()
+        .max_dbs(5)
+        .open(next_path(), USER_DIR)
+        .unwrap();
+
+    let plain = env.create_db("plain", DbFlags::empty()).unwrap();
+    let dups = env.create_db("dups", DbFlags::DbAllowDups).unwrap();
+    {
+        let txn = env.new_transaction().unwrap();
+        txn.bind(&plain).set(&"a", &"1").unwrap();
+        txn.bind(&dups).set(&"a", &"1").unwrap();
+        txn.bind(&dups).set(&"a", &"2").unwrap();
+        txn.commit().unwrap();
+    }
+    // a plain key in main db isn't a database
+    {
+        let main = env.get_default_db(DbFlags::empty()).unwrap();
+        let txn = env.new_transaction().unwrap();
+        txn.bind(&main).set(&"not-a-db", &"value").unwrap();
+        txn.commit().unwrap();
+    }
+
+    let dbs = env.list_dbs().unwrap();
+    let names: Vec<&str> = dbs.iter().map(|db| db.name.as_str()).collect();
+    assert_eq!(names, vec!["dups", "plain"]);
+    assert_eq!(dbs[0].flags, DbFlags::DbAllowDups);
+    assert_eq!(dbs[0].stat.ms_entries, 2);
+    assert_eq!(dbs[1].flags, DbFlags::empty());
+    assert_eq!(dbs[1].stat.ms_entries, 1);
+
+    // databases not opened yet by this process are listed too
+    let path = next_path();
+    {
+        let env = EnvBuilder::new().max_dbs(5).open(&path, USER_DIR).unwrap();
+        env.create_db("ints", DbFlags::DbIntKey).unwrap();
+        env.close().unwrap();
+    }
+    let env = EnvBuilder::new().max_dbs(5).open(&path, USER_DIR).unwrap();
+    let dbs = env.list_dbs().unwrap();
+    assert_eq!(dbs.len(), 1);
+    assert_eq!(dbs[0].name, "ints");
+    assert_eq!(dbs[0].flags, DbFlags::DbIntKey);
+
+    // listed databases aren't opened, so there could be more than max_dbs
+    let path = next_path();
+    {
+        let env = EnvBuilder::new().max_dbs(5).open(&path, USER_DIR).unwrap();
+        for name in ["a", "b", "c"] {
+            let db = env.create_db(name, DbFlags::empty()).unwrap();
+            let txn = env.new_transaction().unwrap();
+            for i in 0..1000u32 {
+                txn.bind(&db).set(&i.to_be_bytes().to_vec(), &name).unwrap();
+            }
+            txn.commit().unwrap();
+        }
+        env.close().unwrap();
+    }
+    let env = EnvBuilder::new().max_dbs(1).open(&path, USER_DIR).unwrap();
+    let dbs = env.list_dbs().unwrap();
+    let names: Vec<&str> = dbs.iter().map(|db| db.name.as_str()).collect();
+    assert_eq!(names, vec!["a", "b", "c"]);
+
+    let db = env.get_db("a", DbFlags::empty()).unwrap();
+    let reader = env.get_reader().unwrap();
+    let stat = reader.bind(&db).stat().unwrap();
+    assert_eq!(dbs[0].stat.ms_entries, 1000);
+    assert_eq!(dbs[0].stat.ms_depth, stat.ms_depth);
+    assert_eq!(dbs[0].stat.ms_leaf_pages, stat.ms_leaf_pages);
+    assert_eq!(dbs[0].stat.ms_branch_pages, stat.ms_branch_pages);
+    assert_eq!(dbs[0].stat.ms_psize, stat.ms_psize);
+}
+
+#[test]
+fn test_rename_db() {
+    let env = EnvBuilder::new()
+        .max_dbs(5)
+        .open(next_path(), USER_DIR)
+        .unwrap();
+
+    let db = env.create_db("old", DbFlags::DbAllowDups).unwrap();
+    env.create_db("taken", DbFlags::empty()).unwrap();
+    {
+        let txn = env.new_transaction().unwrap();
+        txn.bind(&db).set(&"a", &"1").unwrap();
+        txn.bind(&db).set(&"a", &"2").unwrap();
+        txn.bind(&db).set(&"b", &"3").unwrap();
+        txn.commit().unwrap();
+    }
+
+    assert!(matches!(env.rename_db("old", "taken"), Err(KeyExists)));
+    assert!(matches!(env.rename_db("missing", "new"), Err(NotFound)));
+
+    env.rename_db("old", "new").unwrap();
+    assert!(matches!(env.get_db("old", DbFlags::DbAllowDups), Err(NotFound)));
+
+    let db = env.get_db("new", DbFlags::DbAllowDups).unwrap();
+    let reader = env.get_reader().unwrap();
+    let db = reader.bind(&db);
+    let items: Vec<String> = db.item_iter(&"a").unwrap().map(|cv| cv.get_value()).collect();
+    assert_eq!(items, vec!["1".to_owned(), "2".to_owned()]);
+    assert_eq!(db.get::<String>(&"b").unwrap(), "3");
+    assert_eq!(db.stat().unwrap().ms_entries, 3);
+}
+
+#[test]
+fn test_copy_db() {
+    let src_env = EnvBuilder::new()
+        .max_dbs(5)
+        .open(next_path(), USER_DIR)
+        .unwrap();
+    let dst_env = EnvBuilder::new()
+        .max_dbs(5)
+        .open(next_path(), USER_DIR)
+        .unwrap();
+
+    let db = src_env.create_db("tenant", DbFlags::DbAllowDups).unwrap();
+    {
+        let txn = src_env.new_transaction().unwrap();
+        txn.bind(&db).set(&"a", &"1").unwrap();
+        txn.bind(&db).set(&"a", &"2").unwrap();
+        txn.commit().unwrap();
+    }
+
+    src_env.copy_db("tenant", &dst_env, "copy").unwrap();
+    src_env.copy_db("tenant", &src_env, "local-copy").unwrap();
+    assert!(matches!(src_env.copy_db("tenant", &dst_env, "copy"), Err(KeyExists)));
+
+    for (env, name) in [(&dst_env, "copy"), (&src_env, "local-copy"), (&src_env, "tenant")] {
+        let db = env.get_db(name, DbFlags::DbAllowDups).unwrap();
+        let reader = env.get_reader().unwrap();
+        let db = reader.bind(&db);
+        let items: Vec<String> = db.item_iter(&"a").unwrap().map(|cv| cv.get_value()).collect();
+        assert_eq!(items, vec!["1".to_owned(), "2".to_owned()]);
+    }
+}
+
+#[test]
+fn test_copy_db_keeps_comparators() {
+    let env = EnvBuilder::new()
+        .max_dbs(5)
+        .compare_with("by-len", by_len_then_bytes)
+        .dupsort_with("by-len", |a, b| b.cmp(a))
+        .open(next_path(), USER_DIR)
+        .unwrap();
+    let dst_env = EnvBuilder::new()
+        .max_dbs(5)
+        .compare_with("reversed", |a, b| b.cmp(a))
+        .open(next_path(), USER_DIR)
+        .unwrap();
+
+    let db = env.create_db("by-len", DbFlags::DbAllowDups).unwrap();
+    {
+        let txn = env.new_transaction().unwrap();
+        for key in ["ccc", "a", "bb"] {
+            txn.bind(&db).set(&key, &"1").unwrap();
+            txn.bind(&db).set(&key, &"2").unwrap();
+        }
+        txn.commit().unwrap();
+    }
+
+    env.rename_db("by-len", "renamed").unwrap();
+    env.copy_db("renamed", &dst_env, "copy").unwrap();
+    // destination orders keys its own way
+    assert!(matches!(env.copy_db("renamed", &dst_env, "reversed"), Err(StateError(_))));
+
+    for (env, name) in [(&env, "renamed"), (&dst_env, "copy")] {
+        let db = env.get_db(name, DbFlags::DbAllowDups).unwrap();
+        let reader = env.get_reader().unwrap();
+        let db = reader.bind(&db);
+        let keys: Vec<String> = db.iter().unwrap().map(|cv| cv.get_key()).collect();
+        assert_eq!(keys, vec!["a", "bb", "ccc"]);
+        for key in keys.iter() {
+            let items: Vec<String> = db.item_iter(key).unwrap().map(|cv| cv.get_value()).collect();
+            assert_eq!(items, vec!["2", "1"]);
+        }
+    }
+}
+
+#[test]
+fn test_stale_handle_after_del_db() {
+    let env = EnvBuilder::new()
+        .max_dbs(5)
+        .open(next_path(), USER_DIR)
+        .unwrap();
+
+    let db = env.create_db("dropped", DbFlags::empty()).unwrap();
+    let copy = db;
+    {
+        let txn = env.new_transaction().unwrap();
+        txn.bind(&db).del_db().unwrap();
+        assert!(matches!(txn.bind(&copy).get::<String>(&"key"), Err(StaleHandle)));
+        txn.commit().unwrap();
+    }
+
+    // slot is reused by a new db, old handle must not reach it
+    let other = env.create_db("other", DbFlags::empty()).unwrap();
+    let txn = env.new_transaction().unwrap();
+    txn.bind(&other).set(&"key", &"value").unwrap();
+    assert!(matches!(txn.bind(&copy).get::<String>(&"key"), Err(StaleHandle)));
+    assert!(matches!(txn.bind(&copy).set(&"key", &"value"), Err(StaleHandle)));
+    assert!(matches!(txn.bind(&copy).del_db(), Err(StaleHandle)));
+}
+
+#[test]
+fn test_close_db() {
+    let env = EnvBuilder::new()
+        .max_dbs(5)
+        .open(next_path(), USER_DIR)
+        .unwrap();
+
+    let db = env.create_db("closed", DbFlags::empty()).unwrap();
+    {
+        let txn = env.new_transaction().unwrap();
+        txn.bind(&db).set(&"key", &"value").unwrap();
+        assert!(matches!(env.close_db(db), Err(StateError(_))));
+        txn.commit().unwrap();
+    }
+
+    env.close_db(db).unwrap();
+    assert!(matches!(env.close_db(db), Err(StaleHandle)));
+    {
+        let reader = env.get_reader().unwrap();
+        assert!(matches!(reader.bind(&db).get::<String>(&"key"), Err(StaleHandle)));
+    }
+
+    let db = env.get_db("closed", DbFlags::empty()).unwrap();
+    let reader = env.get_reader().unwrap();
+    assert_eq!(reader.bind(&db).get::<String>(&"key").unwrap(), "value");
+}
+
+#[test]
+fn test_close_db_racing_readers() {
+    use std::sync::Arc;
+    use std::sync::atomic::AtomicBool;
+
+    let env = EnvBuilder::new()
+        .max_dbs(5)
+        .open(next_path(), USER_DIR)
+        .unwrap();
+    let db = env.create_db("closed", DbFlags::empty()).unwrap();
+    {
+        let txn = env.new_transaction().unwrap();
+        txn.bind(&db).set(&"key", &"value").unwrap();
+        txn.commit().unwrap();
+    }
+
+    // readers see either the value or a stale handle, never a closed db
+    let closed = Arc::new(AtomicBool::new(false));
+    let threads: Vec<_> = (0..4).map(|_| {
+        let env = env.clone();
+        let closed = closed.clone();
+        thread::spawn(move || {
+            for _ in 0..1000 {
+                let was_closed = closed.load(Ordering::SeqCst);
+                let reader = env.get_reader().unwrap();
+                match reader.bind(&db).get::<String>(&"key") {
+                    Ok(value) => {
+                        assert!(!was_closed);
+                        assert_eq!(value, "value");
+                    }
+                    Err(StaleHandle) => return,
+                    Err(e) => panic!("unexpected error {}", e),
+                }
+            }
+        })
+    }).collect();
+
+    loop {
+        match env.close_db(db) {
+            Ok(()) => break,
+            Err(StateError(_)) => thread::yield_now(),
+            Err(e) => panic!("unexpected error {}", e),
+        }
+    }
+    closed.store(true, Ordering::SeqCst);
+    for t in threads {
+        t.join().unwrap();
+    }
+}
+
+#[test]
+fn test_stale_handle_after_abort() {
+    let env = EnvBuilder::new()
+        .max_dbs(5)
+        .open(next_path(), USER_DIR)
+        .unwrap();
+
+    let existing = env.create_db("existing", DbFlags::empty()).unwrap();
+    let (aborted, reopened) = {
+        let txn = env.new_transaction().unwrap();
+        let aborted = txn.create_db("aborted", DbFlags::empty()).unwrap();
+        let reopened = txn.open_db("existing", DbFlags::empty()).unwrap();
+        txn.abort();
+        (aborted, reopened)
+    };
+
+    env.create_db("new", DbFlags::empty()).unwrap();
+    let txn = env.new_transaction().unwrap();
+    assert!(matches!(txn.bind(&aborted).get::<String>(&"key"), Err(StaleHandle)));
+    // db open before the transaction stays open
+    txn.bind(&existing).set(&"key", &"value").unwrap();
+    txn.bind(&reopened).set(&"key", &"value").unwrap();
+}
+
+fn by_len_then_bytes(a: &[u8], b: &[u8]) -> std::cmp::Ordering {
+    a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+}
+
+#[test]
+fn test_compare_with() {
+    let path = next_path();
+    let builder = EnvBuilder::new()
+        .max_dbs(5)
+        .compare_with("by-len", by_len_then_bytes);
+
+    {
+        let env = builder.clone().open(&path, USER_DIR).unwrap();
+        let db = env.create_db("by-len", DbFlags::empty()).unwrap();
+        let txn = env.new_transaction().unwrap();
+        for key in ["ccc", "a", "bb", "aa"] {
+            txn.bind(&db).set(&key, &key).unwrap();
+        }
+        txn.commit().unwrap();
+        env.close().unwrap();
+    }
+
+    // comparator is installed again on reopening
+    let env = builder.open(&path, USER_DIR).unwrap();
+    let db = env.get_db("by-len", DbFlags::empty()).unwrap();
+    let reader = env.get_reader().unwrap();
+    let db = reader.bind(&db);
+    let keys: Vec<String> = db.iter().unwrap().map(|cv| cv.get_key()).collect();
+    assert_eq!(keys, vec!["a", "aa", "bb", "ccc"]);
+    assert_eq!(db.get::<String>(&"bb").unwrap(), "bb");
+}
+
+#[test]
+fn test_dupsort_with_capturing_closure() {
+    let descending = true;
+    let env = EnvBuilder::new()
+        .max_dbs(5)
+        .dupsort_with("dups", move |a, b| if descending { b.cmp(a) } else { a.cmp(b) })
+        .open(next_path(), USER_DIR)
+        .unwrap();
+
+    let txn = env.new_transaction().unwrap();
+    let db = txn.create_db("dups", DbFlags::DbAllowDups).unwrap();
+    for value in ["1", "3", "2"] {
+        txn.bind(&db).set(&"key", &value).unwrap();
+    }
+    txn.commit().unwrap();
+
+    let reader = env.get_reader().unwrap();
+    let db = reader.bind(&db);
+    let items: Vec<String> = db.item_iter(&"key").unwrap().map(|cv| cv.get_value()).collect();
+    assert_eq!(items, vec!["3", "2", "1"]);
+}
+
+#[test]
+fn test_set_compare_with() {
+    let env = EnvBuilder::new()
+        .max_dbs(5)
+        .open(next_path(), USER_DIR)
+        .unwrap();
+
+    let txn = env.new_transaction().unwrap();
+    let db = txn.create_db("reversed", DbFlags::empty()).unwrap();
+    {
+        let db = txn.bind(&db);
+        db.set_compare_with(|a, b| b.cmp(a)).unwrap();
+        for key in ["a", "c", "b"] {
+            db.set(&key, &key).unwrap();
+        }
+        let keys: Vec<String> = db.iter().unwrap().map(|cv| cv.get_key()).collect();
+        assert_eq!(keys, vec!["c", "b", "a"]);
+    }
+    txn.commit().unwrap();
+}
+
+#[test]
+fn test_comparator_slots_released() {
+    // more environments than there are comparator slots
+    for _ in 0..100 {
+        let env = EnvBuilder::new()
+            .max_dbs(5)
+            .compare_with("db", by_len_then_bytes)
+            .open(next_path(), USER_DIR)
+            .unwrap();
+        let db = env.create_db("db", DbFlags::empty()).unwrap();
+        {
+            let txn = env.new_transaction().unwrap();
+            txn.bind(&db).set(&"key", &"value").unwrap();
+            txn.commit().unwrap();
+        }
+        env.close().unwrap();
+    }
+}
+
+const EXHAUST_PATH_VAR: &str = "LMDB_RS_EXHAUST_PATH";
+
+/// Run in a separate process by `test_comparators_exhausted`, as
+/// comparator slots are shared by all environments of the process
+#[test]
+#[ignore]
+fn exhaust_comparators_in_child_process() {
+    let path = match env::var_os(EXHAUST_PATH_VAR) {
+        Some(path) => path,
+        None => return,
+    };
+    let env = EnvBuilder::new()
+        .max_dbs(70)
+        .open(&path, USER_DIR)
+        .unwrap();
+    {
+        let txn = env.new_transaction().unwrap();
+        for i in 0..64 {
+            let db = txn.create_db(&format!("db{}", i), DbFlags::empty()).unwrap();
+            txn.bind(&db).set_compare_with(|a, b| b.cmp(a)).unwrap();
+        }
+        let db = txn.create_db("db64", DbFlags::empty()).unwrap();
+        assert!(matches!(txn.bind(&db).set_compare_with(|a, b| b.cmp(a)), Err(ComparatorsExhausted)));
+        txn.commit().unwrap();
+    }
+    env.close().unwrap();
+
+    // slots are freed along with the environment
+    let env = EnvBuilder::new()
+        .max_dbs(70)
+        .compare_with("db0", |a, b| b.cmp(a))
+        .open(&path, USER_DIR)
+        .unwrap();
+    env.get_db("db0", DbFlags::empty()).unwrap();
+}
+
+#[test]
+fn test_comparators_exhausted() {
+    use std::process::Command;
+
+    let status = Command::new(env::current_exe().unwrap())
+        .args(["--ignored", "--exact", "tests::exhaust_comparators_in_child_process"])
+        .env(EXHAUST_PATH_VAR, next_path())
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+type SliceCmp = fn(&[u8], &[u8]) -> std::cmp::Ordering;
+type CCmp = extern "C" fn(*const MDB_val, *const MDB_val) -> c_int;
+
+/// Checks that the db orders keys as the comparator does, using both
+/// its `extern "C"` and closure forms
+fn check_comparator(keys: Vec<Vec<u8>>, slice_cmp: SliceCmp, c_cmp: CCmp) {
+    let mut sorted = keys.clone();
+    sorted.sort_by(|a, b| slice_cmp(a, b));
+    sorted.dedup_by(|a, b| slice_cmp(a, b) == std::cmp::Ordering::Equal);
+
+    let env = EnvBuilder::new()
+        .max_dbs(5)
+        .open(next_path(), USER_DIR)
+        .unwrap();
+    let txn = env.new_transaction().unwrap();
+    let c_db = txn.create_db("c", DbFlags::empty()).unwrap();
+    let closure_db = txn.create_db("closure", DbFlags::empty()).unwrap();
+    txn.bind(&c_db).set_compare(c_cmp).unwrap();
+    txn.bind(&closure_db).set_compare_with(slice_cmp).unwrap();
+
+    for handle in [&c_db, &closure_db] {
+        let db = txn.bind(handle);
+        for key in keys.iter() {
+            db.set(key, &"value").unwrap();
+        }
+
+        let stored: Vec<Vec<u8>> = db.iter().unwrap().map(|cv| cv.get_key()).collect();
+        assert_eq!(stored, sorted);
+
+        for a in keys.iter() {
+            for b in keys.iter() {
+                assert_eq!(db.cmp_keys(a, b).unwrap(), slice_cmp(a, b), "{:?} vs {:?}", a, b);
+            }
+        }
+
+        let (start, end) = (&sorted[1], &sorted[sorted.len() - 2]);
+        let in_range: Vec<Vec<u8>> = db.keyrange(start, end).unwrap().map(|cv| cv.get_key()).collect();
+        assert_eq!(in_range, &sorted[1..sorted.len() - 1]);
+    }
+}
+
+#[test]
+fn test_integer_comparators() {
+    let u32s = [7u32, 0, 256, 1, u32::MAX, 65536];
+    check_comparator(u32s.iter().map(|n| n.to_ne_bytes().to_vec()).collect(),
+                     comparators::slice::native_u32, comparators::native_u32);
+
+    let u64s = [7u64, 0, 256, 1, u64::MAX, 1 << 40];
+    check_comparator(u64s.iter().map(|n| n.to_ne_bytes().to_vec()).collect(),
+                     comparators::slice::native_u64, comparators::native_u64);
+
+    let i32s = [7i32, -1, 0, i32::MIN, i32::MAX, -256];
+    check_comparator(i32s.iter().map(|n| n.to_ne_bytes().to_vec()).collect(),
+                     comparators::slice::native_i32, comparators::native_i32);
+
+    let i64s = [7i64, -1, 0, i64::MIN, i64::MAX, -256];
+    check_comparator(i64s.iter().map(|n| n.to_ne_bytes().to_vec()).collect(),
+                     comparators::slice::native_i64, comparators::native_i64);
+
+    check_comparator(i32s.iter().map(|n| n.to_be_bytes().to_vec()).collect(),
+                     comparators::slice::big_endian_i32, comparators::big_endian_i32);
+    check_comparator(i64s.iter().map(|n| n.to_be_bytes().to_vec()).collect(),
+                     comparators::slice::big_endian_i64, comparators::big_endian_i64);
+
+    // values of wrong size go last
+    let mut mixed: Vec<Vec<u8>> = u32s.iter().map(|n| n.to_ne_bytes().to_vec()).collect();
+    mixed.push(b"xy".to_vec());
+    mixed.push(b"abcdefgh".to_vec());
+    check_comparator(mixed, comparators::slice::native_u32, comparators::native_u32);
+}
+
+#[test]
+fn test_byte_comparators() {
+    let words: Vec<Vec<u8>> = ["banana", "Apple", "apple", "b", "APPLE", "cherry", "Ab", "ab"]
+        .iter().map(|w| w.as_bytes().to_vec()).collect();
+    check_comparator(words.clone(), comparators::slice::ascii_case_insensitive,
+                     comparators::ascii_case_insensitive);
+    check_comparator(words.clone(), comparators::slice::reverse_lexicographic,
+                     comparators::reverse_lexicographic);
+    check_comparator(words, comparators::slice::length_then_bytes,
+                     comparators::length_then_bytes);
+}
+
+#[test]
+fn test_tuple_comparator() {
+    use crate::comparators::{decode_tuple, encode_tuple};
+
+    let tuples: Vec<Vec<u8>> = vec![
+        encode_tuple(&[b"user", b"10"]),
+        encode_tuple(&[b"user", b"9"]),
+        encode_tuple(&[b"user"]),
+        encode_tuple(&[b"group", b"zzz"]),
+        encode_tuple(&[b"usera"]),
+        encode_tuple(&[b"user", b"", b"x"]),
+        b"\xff\xff".to_vec(),
+    ];
+    assert_eq!(decode_tuple(&tuples[0]).unwrap(), vec![&b"user"[..], &b"10"[..]]);
+    assert!(decode_tuple(&tuples[6]).is_none());
+    assert_eq!(comparators::slice::tuple(&tuples[2], &tuples[0]), std::cmp::Ordering::Less);
+    // plain bytes would put the longer "usera" first due to length prefix
+    assert_eq!(comparators::slice::tuple(&tuples[4], &tuples[0]), std::cmp::Ordering::Greater);
+
+    check_comparator(tuples, comparators::slice::tuple, comparators::tuple);
+}
+
+#[test]
+fn test_dupsort_comparator() {
+    let env = EnvBuilder::new()
+        .max_dbs(5)
+        .open(next_path(), USER_DIR)
+        .unwrap();
+    let txn = env.new_transaction().unwrap();
+    let db = txn.create_db("dups", DbFlags::DbAllowDups).unwrap();
+    let db = txn.bind(&db);
+    db.set_dupsort(comparators::big_endian_i64).unwrap();
+
+    let values = [5i64, -3, 0, i64::MIN, 42];
+    for value in values.iter() {
+        db.set(&"key", &value.to_be_bytes().to_vec()).unwrap();
+    }
+    let stored: Vec<i64> = db.item_iter(&"key").unwrap()
+        .map(|cv| i64::from_be_bytes(cv.get_value::<Vec<u8>>().try_into().unwrap()))
+        .collect();
+    assert_eq!(stored, vec![i64::MIN, -3, 0, 5, 42]);
+    assert_eq!(db.cmp_items(&(-3i64).to_be_bytes().to_vec(), &5i64.to_be_bytes().to_vec()).unwrap(),
+               std::cmp::Ordering::Less);
+}
+
+#[test]
+fn test_update() {
+    let env = EnvBuilder::new().open(next_path(), USER_DIR).unwrap();
+    let db = env.get_default_db(DbFlags::empty()).unwrap();
+    let txn = env.new_transaction().unwrap();
+    let db = txn.bind(&db);
+
+    db.update(&"key", |current| {
+        assert!(current.is_none());
+        Some(b"a".to_vec())
+    }).unwrap();
+    db.update(&"key", |current| {
+        let mut value = current.unwrap().to_vec();
+        value.extend_from_slice(b"bc");
+        Some(value)
+    }).unwrap();
+    assert_eq!(db.get::<String>(&"key").unwrap(), "abc");
+
+    db.update(&"key", |_| None).unwrap();
+    assert!(matches!(db.get::<String>(&"key"), Err(NotFound)));
+    // nothing to delete
+    db.update(&"key", |_| None).unwrap();
+}
+
+#[test]
+fn test_update_duplicates() {
+    let env = EnvBuilder::new().open(next_path(), USER_DIR).unwrap();
+    let db = env.get_default_db(DbFlags::DbAllowDups).unwrap();
+    let txn = env.new_transaction().unwrap();
+    let db = txn.bind(&db);
+
+    db.set(&"key", &"b").unwrap();
+    db.set(&"key", &"c").unwrap();
+    db.update(&"key", |current| {
+        assert_eq!(current.unwrap(), b"b");
+        Some(b"d".to_vec())
+    }).unwrap();
+    let items: Vec<String> = db.item_iter(&"key").unwrap().map(|cv| cv.get_value()).collect();
+    assert_eq!(items, vec!["c", "d"]);
+}
+
+#[test]
+fn test_get_or_insert_with() {
+    let env = EnvBuilder::new().open(next_path(), USER_DIR).unwrap();
+    let db = env.get_default_db(DbFlags::empty()).unwrap();
+    let txn = env.new_transaction().unwrap();
+    let db = txn.bind(&db);
+
+    let value: String = db.get_or_insert_with(&"key", || "first").unwrap();
+    assert_eq!(value, "first");
+    let value: String = db.get_or_insert_with(&"key", || -> &str { panic!("must not be called") }).unwrap();
+    assert_eq!(value, "first");
+}
+
+#[test]
+fn test_compare_and_swap() {
+    let env = EnvBuilder::new().open(next_path(), USER_DIR).unwrap();
+    let db = env.get_default_db(DbFlags::empty()).unwrap();
+    let txn = env.new_transaction().unwrap();
+    let db = txn.bind(&db);
+
+    assert!(!db.compare_and_swap(&"key", Some(&"old"), Some(&"new")).unwrap());
+    assert!(db.compare_and_swap(&"key", None::<&&str>, Some(&"old")).unwrap());
+    assert!(!db.compare_and_swap(&"key", None::<&&str>, Some(&"other")).unwrap());
+    assert!(db.compare_and_swap(&"key", Some(&"old"), Some(&"new")).unwrap());
+    assert_eq!(db.get::<String>(&"key").unwrap(), "new");
+
+    assert!(!db.compare_and_swap(&"key", Some(&"old"), None::<&&str>).unwrap());
+    assert!(db.compare_and_swap(&"key", Some(&"new"), None::<&&str>).unwrap());
+    assert!(matches!(db.get::<String>(&"key"), Err(NotFound)));
+}
+
+#[test]
+fn test_counter_add() {
+    let env = EnvBuilder::new().open(next_path(), USER_DIR).unwrap();
+    let db = env.get_default_db(DbFlags::empty()).unwrap();
+    let txn = env.new_transaction().unwrap();
+    let db = txn.bind(&db);
+
+    assert_eq!(db.counter_add(&"hits", 5).unwrap(), 5);
+    assert_eq!(db.counter_add(&"hits", -7).unwrap(), -2);
+    let stored: Vec<u8> = db.get(&"hits").unwrap();
+    assert_eq!(stored, (-2i64).to_be_bytes());
+    db.set(&"preset", &&40i64).unwrap();
+    assert_eq!(db.counter_add(&"preset", 2).unwrap(), 42);
+
+    assert!(matches!(db.counter_add(&"hits", i64::MIN), Err(StateError(_))));
+    assert_eq!(db.counter_add(&"hits", 0).unwrap(), -2);
+
+    db.set(&"text", &"not a number").unwrap();
+    assert!(matches!(db.counter_add(&"text", 1), Err(StateError(_))));
+}
+
+#[test]
+fn test_get_many() {
+    let env = EnvBuilder::new()
+        .max_dbs(5)
+        .compare_with("reversed", comparators::slice::reverse_lexicographic)
+        .open(next_path(), USER_DIR)
+        .unwrap();
+
+    for name in ["plain", "reversed"] {
+        let db = env.create_db(name, DbFlags::empty()).unwrap();
+        let txn = env.new_transaction().unwrap();
+        let db = txn.bind(&db);
+        for i in (0..100).step_by(2) {
+            db.set(&format!("key{:03}", i), &format!("value{}", i)).unwrap();
+        }
+
+        let keys = ["key010", "key999", "key011", "key000", "key010", "key098", "aaa"];
+        let values: Vec<Option<String>> = db.get_many(&keys).unwrap();
+        assert_eq!(values, vec![
+            Some("value10".to_owned()),
+            None,
+            None,
+            Some("value0".to_owned()),
+            Some("value10".to_owned()),
+            Some("value98".to_owned()),
+            None,
+        ]);
+        assert!(db.get_many::<&str, String>(&[]).unwrap().is_empty());
+    }
+}
+
+#[test]
+fn test_contains() {
+    let env = EnvBuilder::new().open(next_path(), USER_DIR).unwrap();
+    let db = env.get_default_db(DbFlags::DbAllowDups).unwrap();
+    let txn = env.new_transaction().unwrap();
+    let db = txn.bind(&db);
+
+    db.set(&"key", &"ab").unwrap();
+    db.set(&"key", &"abcd").unwrap();
+    db.set(&"empty", &"").unwrap();
+
+    assert!(db.contains(&"key").unwrap());
+    assert!(db.contains(&"empty").unwrap());
+    assert!(!db.contains(&"missing").unwrap());
+
+    assert_eq!(db.value_len(&"key").unwrap(), Some(2));
+    assert_eq!(db.value_len(&"empty").unwrap(), Some(0));
+    assert_eq!(db.value_len(&"missing").unwrap(), None);
+
+    assert!(db.contains_item(&"key", &"abcd").unwrap());
+    assert!(!db.contains_item(&"key", &"abc").unwrap());
+    assert!(!db.contains_item(&"missing", &"ab").unwrap());
+}
+
+#[test]
+fn test_len_and_count_range() {
+    let env = EnvBuilder::new()
+        .max_dbs(5)
+        .open(next_path(), USER_DIR)
+        .unwrap();
+    let plain = env.create_db("plain", DbFlags::empty()).unwrap();
+    let dups = env.create_db("dups", DbFlags::DbAllowDups).unwrap();
+    let txn = env.new_transaction().unwrap();
+    let plain = txn.bind(&plain);
+    let dups = txn.bind(&dups);
+
+    assert!(plain.is_empty().unwrap());
+    for key in ["a", "b", "c", "d"] {
+        plain.set(&key, &"value").unwrap();
+        for item in ["1", "2", "3"] {
+            dups.set(&key, &item).unwrap();
+        }
+    }
+
+    assert!(!plain.is_empty().unwrap());
+    assert_eq!(plain.len().unwrap(), 4);
+    assert_eq!(plain.distinct_keys().unwrap(), 4);
+    assert_eq!(dups.len().unwrap(), 12);
+    assert_eq!(dups.distinct_keys().unwrap(), 4);
+
+    assert_eq!(plain.count_range::<&str, _>(..).unwrap(), 4);
+    assert_eq!(plain.count_range("b".."d").unwrap(), 2);
+    assert_eq!(plain.count_range("b"..="d").unwrap(), 3);
+    assert_eq!(plain.count_range("bb"..).unwrap(), 2);
+    assert_eq!(plain.count_range(..="a").unwrap(), 1);
+    assert_eq!(plain.count_range::<&str, _>((Bound::Excluded("a"), Bound::Excluded("d"))).unwrap(), 2);
+    assert_eq!(plain.count_range("x"..).unwrap(), 0);
+
+    assert_eq!(dups.count_range("b"..="c").unwrap(), 6);
+    assert_eq!(dups.count_range::<&str, _>((Bound::Excluded("a"), Bound::Unbounded)).unwrap(), 9);
+}
+
+#[test]
+fn test_exact_size_hint() {
+    let env = EnvBuilder::new()
+        .max_dbs(5)
+        .open(next_path(), USER_DIR)
+        .unwrap();
+    let plain = env.create_db("plain", DbFlags::empty()).unwrap();
+    let dups = env.create_db("dups", DbFlags::DbAllowDups).unwrap();
+    let txn = env.new_transaction().unwrap();
+    let plain = txn.bind(&plain);
+    let dups = txn.bind(&dups);
+
+    assert_eq!(plain.iter().unwrap().size_hint(), (0, Some(0)));
+    for key in ["a", "b", "c"] {
+        plain.set(&key, &"value").unwrap();
+        dups.set(&"key", &key).unwrap();
+    }
+
+    let mut iter = plain.iter().unwrap();
+    assert_eq!(iter.size_hint(), (3, Some(3)));
+    iter.next();
+    assert_eq!(iter.size_hint(), (2, Some(2)));
+    assert_eq!(iter.count(), 2);
+
+    let mut items = dups.item_iter(&"key").unwrap();
+    assert_eq!(items.size_hint(), (3, Some(3)));
+    items.next();
+    items.next();
+    assert_eq!(items.size_hint(), (1, Some(1)));
+    items.next();
+    assert_eq!(items.size_hint(), (0, Some(0)));
+
+    // ranges are counted when the hint is asked for
+    let mut range = plain.keyrange_from(&"b").unwrap();
+    range.next();
+    assert_eq!(range.size_hint(), (1, Some(1)));
+    assert_eq!(plain.keyrange(&"a", &"b").unwrap().size_hint(), (2, Some(2)));
+    assert_eq!(plain.keyrange_to(&"a").unwrap().size_hint(), (0, Some(0)));
+    assert_eq!(plain.keyrange_from(&"z").unwrap().size_hint(), (0, Some(0)));
+
+    // so are keys of a db with duplicates
+    dups.set(&"other", &"value").unwrap();
+    let mut keys = dups.iter().unwrap();
+    assert_eq!(keys.size_hint(), (2, Some(2)));
+    keys.next();
+    assert_eq!(keys.size_hint(), (1, Some(1)));
+    let mut items = dups.item_range(&"key", ..="b").unwrap();
+    items.next();
+    assert_eq!(items.size_hint(), (1, Some(1)));
+}
+
+#[test]
+fn test_borrowed_values_outlive_cursor_steps() {
+    let env = EnvBuilder::new().open(next_path(), USER_DIR).unwrap();
+    let db = env.get_default_db(DbFlags::empty()).unwrap();
+    let txn = env.new_transaction().unwrap();
+    {
+        let db = txn.bind(&db);
+        for key in ["a", "b", "c"] {
+            db.set(&key, &key.repeat(2)).unwrap();
+        }
+        db.set(&&7u64, &"seven").unwrap();
+    }
+    txn.commit().unwrap();
+
+    let reader = env.get_reader().unwrap();
+    let db = reader.bind(&db);
+    // borrowed data belongs to the transaction, not to the cursor position
+    let pairs: Vec<(&[u8], &str)> = db.keyrange_from(&"a").unwrap()
+        .map(|cv| cv.get())
+        .collect();
+    assert_eq!(pairs, vec![(&b"a"[..], "aa"), (&b"b"[..], "bb"), (&b"c"[..], "cc")]);
+
+    let mut cursor = db.new_cursor().unwrap();
+    cursor.to_key(&"a").unwrap();
+    let first: &str = cursor.get_value().unwrap();
+    cursor.to_next_key().unwrap();
+    let second: &str = cursor.get_value().unwrap();
+    assert_eq!((first, second), ("aa", "bb"));
+
+    let seven: &str = db.get(&&7u64).unwrap();
+    assert_eq!(seven, "seven");
+}
+
+#[test]
+fn test_values_survive_writes_in_write_txn() {
+    let env = EnvBuilder::new().max_dbs(5).open(next_path(), USER_DIR).unwrap();
+    let db = env.create_db("dups", DbFlags::DbAllowDups).unwrap();
+    let txn = env.new_transaction().unwrap();
+    let db = txn.bind(&db);
+    for key in ["a", "b", "c"] {
+        db.set(&key, &key.repeat(100)).unwrap();
+    }
+
+    let items: Vec<_> = db.iter().unwrap().collect();
+    let mut groups = db.iter_groups().unwrap();
+    let group = groups.next().unwrap().unwrap();
+    // rewrite the pages items were read from
+    for key in ["a", "b", "c"] {
+        db.del(&key).unwrap();
+    }
+    for i in 0..1000u32 {
+        db.set(&format!("key{:04}", i), &"x".repeat(100)).unwrap();
+    }
+    let pairs: Vec<(String, String)> = items.iter().map(|cv| cv.get()).collect();
+    assert_eq!(pairs[1], ("b".to_owned(), "b".repeat(100)));
+    assert_eq!(group.key::<String>(), "a");
+}
+
+#[test]
+fn test_u64_keys_are_big_endian() {
+    let env = EnvBuilder::new().open(next_path(), USER_DIR).unwrap();
+    let db = env.get_default_db(DbFlags::empty()).unwrap();
+    let txn = env.new_transaction().unwrap();
+    {
+        let db = txn.bind(&db);
+        for key in [1u64 << 40, 256, 1] {
+            db.set(&&key, &key.to_string()).unwrap();
+        }
+        db.set(&&-1i64, &"minus one").unwrap();
+    }
+    txn.commit().unwrap();
+
+    let reader = env.get_reader().unwrap();
+    let db = reader.bind(&db);
+    let keys: Vec<Vec<u8>> = db.iter().unwrap().map(|cv| cv.get_key()).collect();
+    assert_eq!(keys, vec![
+        1u64.to_be_bytes().to_vec(),
+        256u64.to_be_bytes().to_vec(),
+        (1u64 << 40).to_be_bytes().to_vec(),
+        (-1i64).to_be_bytes().to_vec(),
+    ]);
+    let value: &str = db.get(&&256u64).unwrap();
+    assert_eq!(value, "256");
+}
+
+#[test]
+fn test_decode_inline_value() {
+    let key = (&5u64).to_mdb_value();
+    assert_eq!(Vec::<u8>::from_mdb_value(&key), 5u64.to_be_bytes().to_vec());
+
+    let env = EnvBuilder::new().open(next_path(), USER_DIR).unwrap();
+    let db = env.get_default_db(DbFlags::empty()).unwrap();
+    let txn = env.new_transaction().unwrap();
+    txn.bind(&db).set(&"key", &&5u64).unwrap();
+    txn.commit().unwrap();
+
+    let reader = env.get_reader().unwrap();
+    let db = reader.bind(&db);
+    let value: &[u8] = db.get(&"key").unwrap();
+    assert_eq!(value, 5u64.to_be_bytes());
+}
+
+#[test]
+fn test_write_flags() {
+    let env = EnvBuilder::new()
+        .max_dbs(5)
+        .open(next_path(), USER_DIR)
+        .unwrap();
+    let plain = env.create_db("plain", DbFlags::empty()).unwrap();
+    let dups = env.create_db("dups", DbFlags::DbAllowDups).unwrap();
+    let fixed = env.create_db("fixed", DbFlags::DbAllowDups | DbFlags::DbDupFixed).unwrap();
+    let txn = env.new_transaction().unwrap();
+    let plain = txn.bind(&plain);
+    let dups = txn.bind(&dups);
+    let fixed = txn.bind(&fixed);
+
+    plain.put_with_flags(&"b", &"1", WriteFlags::NoOverwrite).unwrap();
+    assert!(matches!(plain.put_with_flags(&"b", &"2", WriteFlags::NoOverwrite), Err(KeyExists)));
+    assert!(matches!(plain.put_with_flags(&"a", &"1", WriteFlags::Append), Err(KeyExists)));
+    plain.reserve(&"c", 8, |buf| buf.copy_from_slice(b"reserved")).unwrap();
+    assert_eq!(plain.get::<String>(&"c").unwrap(), "reserved");
+
+    for flags in [WriteFlags::NoDupData, WriteFlags::AppendDup, WriteFlags::Current, WriteFlags::Multiple, WriteFlags::Reserve] {
+        assert!(matches!(plain.put_with_flags(&"d", &"1", flags), Err(InvalidWriteFlags { .. })));
+    }
+    assert!(matches!(dups.reserve(&"k", 1, |buf| buf[0] = b'1'), Err(InvalidWriteFlags { .. })));
+    assert!(plain.get::<()>(&"d").is_err());
+
+    dups.put_with_flags(&"k", &"1", WriteFlags::NoDupData).unwrap();
+    dups.put_with_flags(&"k", &"2", WriteFlags::AppendDup).unwrap();
+    assert!(matches!(dups.put_with_flags(&"k", &"1", WriteFlags::NoDupData), Err(KeyExists)));
+    assert!(matches!(dups.put_with_flags(&"k", &"0", WriteFlags::AppendDup), Err(KeyExists)));
+    assert_eq!(dups.item_iter(&"k").unwrap().count(), 2);
+
+    let mut cursor = plain.new_cursor().unwrap();
+    cursor.to_key(&"b").unwrap();
+    cursor.set(&"b", &"3", WriteFlags::Current).unwrap();
+    assert!(matches!(cursor.set(&"e", &"1", WriteFlags::AppendDup), Err(InvalidWriteFlags { .. })));
+    assert!(matches!(cursor.set_multiple(&"e", b"12", 1), Err(InvalidWriteFlags { .. })));
+    assert_eq!(plain.get::<String>(&"b").unwrap(), "3");
+
+    let mut cursor = fixed.new_cursor().unwrap();
+    assert_eq!(cursor.set_multiple(&"k", b"c1b2a3", 2).unwrap(), 3);
+    assert!(matches!(cursor.set_multiple(&"k", b"abc", 2), Err(StateError(_))));
+    let items: Vec<String> = fixed.item_iter(&"k").unwrap().map(|cv| cv.get_value()).collect();
+    assert_eq!(items, vec!["a3", "b2", "c1"]);
+}
+
+#[test]
+fn test_cursor_seek() {
+    let env = EnvBuilder::new()
+        .max_dbs(5)
+        .open(next_path(), USER_DIR)
+        .unwrap();
+    let plain = env.create_db("plain", DbFlags::empty()).unwrap();
+    let dups = env.create_db("dups", DbFlags::DbAllowDups).unwrap();
+    let txn = env.new_transaction().unwrap();
+    {
+        let mut cursor = txn.bind(&plain).new_cursor().unwrap();
+        assert_eq!(cursor.first::<String, String>().unwrap(), None);
+        assert_eq!(cursor.seek_floor::<String, String>(&"b").unwrap(), None);
+    }
+    for key in ["b", "d", "f"] {
+        txn.bind(&plain).set(&key, &key.to_uppercase()).unwrap();
+    }
+    for (key, value) in [("a", "1"), ("a", "2"), ("c", "1"), ("c", "2")] {
+        txn.bind(&dups).set(&key, &value).unwrap();
+    }
+    txn.commit().unwrap();
+
+    let reader = env.get_reader().unwrap();
+    let plain = reader.bind(&plain);
+    let dups = reader.bind(&dups);
+    let mut cursor = plain.new_cursor().unwrap();
+    assert_eq!(cursor.seek_ceil(&"c").unwrap(), Some(("d", "D")));
+    assert_eq!(cursor.seek_ceil(&"d").unwrap(), Some(("d", "D")));
+    assert_eq!(cursor.seek_ceil::<&str, &str>(&"g").unwrap(), None);
+    assert_eq!(cursor.seek_floor(&"c").unwrap(), Some(("b", "B")));
+    assert_eq!(cursor.seek_floor(&"d").unwrap(), Some(("d", "D")));
+    assert_eq!(cursor.seek_floor(&"z").unwrap(), Some(("f", "F")));
+    assert_eq!(cursor.seek_floor::<&str, &str>(&"a").unwrap(), None);
+    assert_eq!(cursor.seek_exact(&"f").unwrap(), Some(("f", "F")));
+    assert_eq!(cursor.seek_exact::<&str, &str>(&"e").unwrap(), None);
+
+    assert_eq!(cursor.first().unwrap(), Some(("b", "B")));
+    assert_eq!(cursor.next().unwrap(), Some(("d", "D")));
+    assert_eq!(cursor.prev().unwrap(), Some(("b", "B")));
+    assert_eq!(cursor.prev::<&str, &str>().unwrap(), None);
+    assert_eq!(cursor.last().unwrap(), Some(("f", "F")));
+    assert_eq!(cursor.next::<&str, &str>().unwrap(), None);
+
+    let mut cursor = dups.new_cursor().unwrap();
+    assert_eq!(cursor.seek_floor(&"b").unwrap(), Some(("a", "2")));
+    assert_eq!(cursor.seek_floor(&"c").unwrap(), Some(("c", "1")));
+    assert_eq!(cursor.next().unwrap(), Some(("c", "2")));
+    assert_eq!(cursor.seek_ceil(&"b").unwrap(), Some(("c", "1")));
+}
+
+#[test]
+fn test_iter_mut() {
+    let env = EnvBuilder::new()
+        .max_dbs(5)
+        .open(next_path(), USER_DIR)
+        .unwrap();
+    let plain = env.create_db("plain", DbFlags::empty()).unwrap();
+    let dups = env.create_db("dups", DbFlags::DbAllowDups).unwrap();
+    let txn = env.new_transaction().unwrap();
+    let plain = txn.bind(&plain);
+    let dups = txn.bind(&dups);
+
+    // enough keys to span several pages
+    for i in 0..1000 {
+        plain.set(&format!("key{:04}", i), &format!("{}", i)).unwrap();
+    }
+    let mut iter = plain.iter_mut().unwrap();
+    let mut seen = 0;
+    while let Some(mut entry) = iter.next().unwrap() {
+        seen += 1;
+        let n: u32 = entry.value::<String>().parse().unwrap();
+        if n % 2 == 0 {
+            entry.delete().unwrap();
+        } else {
+            entry.replace(&format!("odd {}", n)).unwrap();
+            assert_eq!(entry.value::<String>(), format!("odd {}", n));
+        }
+    }
+    assert_eq!(seen, 1000);
+    assert_eq!(plain.len().unwrap(), 500);
+    assert_eq!(plain.get::<String>(&"key0999").unwrap(), "odd 999");
+    assert!(plain.get::<String>(&"key0998").is_err());
+
+    let mut iter = plain.range_mut("key0100".."key0200").unwrap();
+    while let Some(entry) = iter.next().unwrap() {
+        entry.delete().unwrap();
+    }
+    assert_eq!(plain.len().unwrap(), 450);
+    assert_eq!(plain.count_range("key0100".."key0200").unwrap(), 0);
+    assert_eq!(plain.count_range("key0200"..).unwrap(), 400);
+
+    let mut iter = plain.iter_mut().unwrap();
+    while let Some(entry) = iter.next().unwrap() {
+        entry.delete().unwrap();
+    }
+    assert!(plain.is_empty().unwrap());
+    assert!(plain.iter_mut().unwrap().next().unwrap().is_none());
+
+    for (key, value) in [("a", "1"), ("a", "2"), ("b", "1"), ("b", "2"), ("b", "3"), ("c", "1")] {
+        dups.set(&key, &value).unwrap();
+    }
+    let mut iter = dups.iter_mut().unwrap();
+    let mut seen = Vec::new();
+    while let Some(mut entry) = iter.next().unwrap() {
+        seen.push(format!("{}{}", entry.key::<String>(), entry.value::<String>()));
+        assert!(matches!(entry.replace(&"x"), Err(StateError(_))));
+        if entry.key::<String>() != "b" || entry.value::<String>() == "2" {
+            entry.delete().unwrap();
+        }
+    }
+    assert_eq!(seen, vec!["a1", "a2", "b1", "b2", "b3", "c1"]);
+    let left: Vec<(String, String)> = ["a", "b", "c"].iter()
+        .flat_map(|k| dups.item_iter(k).unwrap().map(|cv| cv.get()).collect::<Vec<_>>())
+        .collect();
+    assert_eq!(left, vec![("b".to_owned(), "1".to_owned()), ("b".to_owned(), "3".to_owned())]);
+}
+
+#[test]
+fn test_join() {
+    let env = EnvBuilder::new()
+        .max_dbs(5)
+        .open(next_path(), USER_DIR)
+        .unwrap();
+    let left = env.create_db("left", DbFlags::empty()).unwrap();
+    let right = env.create_db("right", DbFlags::empty()).unwrap();
+    let extra = env.create_db("extra", DbFlags::DbAllowDups).unwrap();
+    let empty = env.create_db("empty", DbFlags::empty()).unwrap();
+    let txn = env.new_transaction().unwrap();
+    for key in ["a", "b", "c", "e", "h"] {
+        txn.bind(&left).set(&key, &format!("l{}", key)).unwrap();
+    }
+    for key in ["b", "c", "d", "h", "i"] {
+        txn.bind(&right).set(&key, &format!("r{}", key)).unwrap();
+    }
+    for (key, value) in [("c", "x1"), ("c", "x2"), ("h", "x1")] {
+        txn.bind(&extra).set(&key, &value).unwrap();
+    }
+    txn.commit().unwrap();
+
+    let reader = env.get_reader().unwrap();
+    let left = reader.bind(&left);
+    let right = reader.bind(&right);
+    let extra = reader.bind(&extra);
+    let keys = |join: Join<ReadOnly>| -> Vec<String> { join.map(|jv| jv.unwrap().get_key()).collect() };
+    let cursors = || vec![left.new_cursor().unwrap(), right.new_cursor().unwrap()];
+    assert_eq!(keys(Join::inner(cursors()).unwrap()), vec!["b", "c", "h"]);
+    assert_eq!(keys(Join::left(cursors()).unwrap()), vec!["a", "b", "c", "e", "h"]);
+    assert_eq!(keys(Join::union(cursors()).unwrap()), vec!["a", "b", "c", "d", "e", "h", "i"]);
+    assert_eq!(keys(Join::difference(cursors()).unwrap()), vec!["a", "e"]);
+
+    let rows: Vec<(&str, Option<&str>, Option<&str>)> = Join::left(cursors()).unwrap()
+        .map(|jv| jv.unwrap())
+        .map(|jv| (jv.get_key(), jv.get_value(0), jv.get_value(1)))
+        .collect();
+    assert_eq!(rows[0], ("a", Some("la"), None));
+    assert_eq!(rows[1], ("b", Some("lb"), Some("rb")));
+
+    let union: Vec<(&str, Option<&str>, Option<&str>)> = Join::union(cursors()).unwrap()
+        .map(|jv| jv.unwrap())
+        .map(|jv| (jv.get_key(), jv.get_value(0), jv.get_value(1)))
+        .collect();
+    assert_eq!(union[3], ("d", None, Some("rd")));
+    assert_eq!(union[6], ("i", None, Some("ri")));
+
+    let three = vec![left.new_cursor().unwrap(), right.new_cursor().unwrap(), extra.new_cursor().unwrap()];
+    let rows: Vec<(&str, Option<&str>)> = Join::inner(three).unwrap()
+        .map(|jv| jv.unwrap())
+        .map(|jv| (jv.get_key(), jv.get_value(2)))
+        .collect();
+    assert_eq!(rows, vec![("c", Some("x1")), ("h", Some("x1"))]);
+    let three = vec![left.new_cursor().unwrap(), right.new_cursor().unwrap(), extra.new_cursor().unwrap()];
+    assert_eq!(keys(Join::difference(three).unwrap()), vec!["a", "e"]);
+
+    let empty = reader.bind(&empty);
+    assert!(keys(Join::inner(vec![left.new_cursor().unwrap(), empty.new_cursor().unwrap()]).unwrap()).is_empty());
+    assert_eq!(keys(Join::difference(vec![left.new_cursor().unwrap(), empty.new_cursor().unwrap()]).unwrap()).len(), 5);
+    assert!(matches!(Join::<ReadOnly>::inner(Vec::new()), Err(StateError(_))));
+
+    let other_env = EnvBuilder::new().open(next_path(), USER_DIR).unwrap();
+    let handle = other_env.get_default_db(DbFlags::empty()).unwrap();
+    let other_reader = other_env.get_reader().unwrap();
+    let other = other_reader.bind(&handle);
+    assert!(matches!(Join::inner(vec![left.new_cursor().unwrap(), other.new_cursor().unwrap()]), Err(StateError(_))));
+}
+
+#[test]
+fn test_iter_groups() {
+    let env = EnvBuilder::new()
+        .max_dbs(5)
+        .open(next_path(), USER_DIR)
+        .unwrap();
+    let plain = env.create_db("plain", DbFlags::empty()).unwrap();
+    let dups = env.create_db("dups", DbFlags::DbAllowDups).unwrap();
+    let txn = env.new_transaction().unwrap();
+    assert!(txn.bind(&dups).iter_groups().unwrap().next().unwrap().is_none());
+    for (key, value) in [("a", "1"), ("a", "2"), ("a", "3"), ("b", "1"), ("c", "1"), ("c", "2")] {
+        txn.bind(&dups).set(&key, &value).unwrap();
+        txn.bind(&plain).set(&key, &value).unwrap();
+    }
+    txn.commit().unwrap();
+
+    let reader = env.get_reader().unwrap();
+    let plain = reader.bind(&plain);
+    let dups = reader.bind(&dups);
+
+    let mut groups = dups.iter_groups().unwrap();
+    let mut seen = Vec::new();
+    while let Some(mut group) = groups.next().unwrap() {
+        let key: &str = group.key();
+        let count = group.item_count().unwrap();
+        let items = group.items();
+        assert_eq!(items.size_hint(), (count, Some(count)));
+        let values: Vec<&str> = items.map(|cv| cv.get_value()).collect();
+        assert_eq!(values.len(), count);
+        seen.push((key, values));
+    }
+    assert_eq!(seen, vec![("a", vec!["1", "2", "3"]), ("b", vec!["1"]), ("c", vec!["1", "2"])]);
+
+    // partially consumed or skipped items don't disturb the groups
+    let mut groups = dups.iter_groups().unwrap();
+    let mut keys = Vec::new();
+    while let Some(mut group) = groups.next().unwrap() {
+        if group.key::<&str>() == "a" {
+            assert_eq!(group.items().nth(1).unwrap().get_value::<&str>(), "2");
+            assert_eq!(group.items().next().unwrap().get_value::<&str>(), "1");
+        }
+        keys.push(group.key::<String>());
+    }
+    assert_eq!(keys, vec!["a", "b", "c"]);
+
+    let mut groups = plain.iter_groups().unwrap();
+    let mut seen = Vec::new();
+    while let Some(mut group) = groups.next().unwrap() {
+        assert_eq!(group.item_count().unwrap(), 1);
+        let values: Vec<&str> = group.items().map(|cv| cv.get_value()).collect();
+        seen.push((group.key::<&str>(), values));
+    }
+    assert_eq!(seen, vec![("a", vec!["3"]), ("b", vec!["1"]), ("c", vec!["2"])]);
+}
+
+#[test]
+fn test_item_range() {
+    let env = EnvBuilder::new()
+        .max_dbs(5)
+        .open(next_path(), USER_DIR)
+        .unwrap();
+    let plain = env.create_db("plain", DbFlags::empty()).unwrap();
+    let dups = env.create_db("dups", DbFlags::DbAllowDups).unwrap();
+    let txn = env.new_transaction().unwrap();
+    let plain = txn.bind(&plain);
+    let dups = txn.bind(&dups);
+    for ts in ["10", "20", "30", "40"] {
+        dups.set(&"alice", &ts).unwrap();
+    }
+    dups.set(&"bob", &"15").unwrap();
+    dups.set(&"carol", &"50").unwrap();
+
+    let items = |iter: CursorIterator<CursorItemRangeIter, _>| -> Vec<String> {
+        iter.map(|cv| cv.get_value()).collect()
+    };
+    assert_eq!(items(dups.item_range(&"alice", "20".."40").unwrap()), vec!["20", "30"]);
+    assert_eq!(items(dups.item_range(&"alice", "15"..="40").unwrap()), vec!["20", "30", "40"]);
+    assert_eq!(items(dups.item_range(&"alice", ..="10").unwrap()), vec!["10"]);
+    assert_eq!(items(dups.item_range::<_, &str, _>(&"alice", (Bound::Excluded("10"), Bound::Unbounded)).unwrap()),
+               vec!["20", "30", "40"]);
+    assert_eq!(items(dups.item_range::<_, &str, _>(&"alice", ..).unwrap()).len(), 4);
+    assert!(items(dups.item_range(&"alice", "41"..).unwrap()).is_empty());
+    assert!(items(dups.item_range(&"alice", "21".."29").unwrap()).is_empty());
+    assert!(items(dups.item_range(&"dave", "10"..).unwrap()).is_empty());
+
+    assert_eq!(items(dups.item_range_rev(&"alice", "20".."40").unwrap()), vec!["30", "20"]);
+    assert_eq!(items(dups.item_range_rev(&"alice", "15"..="40").unwrap()), vec!["40", "30", "20"]);
+    assert_eq!(items(dups.item_range_rev(&"alice", ..="35").unwrap()), vec!["30", "20", "10"]);
+    assert_eq!(items(dups.item_range_rev(&"alice", "25"..).unwrap()), vec!["40", "30"]);
+    assert_eq!(items(dups.item_range_rev(&"alice", ..="99").unwrap()), vec!["40", "30", "20", "10"]);
+    assert!(items(dups.item_range_rev(&"alice", .."10").unwrap()).is_empty());
+    assert!(items(dups.item_range_rev(&"dave", ..="99").unwrap()).is_empty());
+    assert_eq!(items(dups.item_range_rev(&"bob", ..="99").unwrap()), vec!["15"]);
+
+    let keys: Vec<String> = dups.item_range(&"bob", "10"..).unwrap().map(|cv| cv.get_key()).collect();
+    assert_eq!(keys, vec!["bob"]);
+    assert!(matches!(plain.item_range(&"alice", "1"..), Err(StateError(_))));
+}
+
+#[test]
+fn test_page() {
+    let env = EnvBuilder::new()
+        .max_dbs(5)
+        .open(next_path(), USER_DIR)
+        .unwrap();
+    let plain = env.create_db("plain", DbFlags::empty()).unwrap();
+    let dups = env.create_db("dups", DbFlags::DbAllowDups).unwrap();
+    let txn = env.new_transaction().unwrap();
+    let plain = txn.bind(&plain);
+    let dups = txn.bind(&dups);
+
+    fn entries(page: &Page<ReadWrite>) -> Vec<String> {
+        page.entries.iter().map(|cv| {
+            let (k, v): (String, String) = cv.get();
+            format!("{}{}", k, v)
+        }).collect()
+    }
+
+    fn collect(db: &Database<ReadWrite>, direction: Direction, limit: usize) -> Vec<Vec<String>> {
+        let mut pages = Vec::new();
+        let mut token = None;
+        loop {
+            let page = db.page(token.as_ref(), limit, direction).unwrap();
+            pages.push(entries(&page));
+            match page.next {
+                // tokens survive a round trip through bytes
+                Some(next) => token = PageToken::from_bytes(next.as_bytes()),
+                None => return pages,
+            }
+        }
+    }
+
+    assert_eq!(collect(&plain, Direction::Forward, 2), vec![Vec::<String>::new()]);
+    for key in ["a", "b", "c", "d", "e"] {
+        plain.set(&key, &"").unwrap();
+    }
+    assert_eq!(collect(&plain, Direction::Forward, 2), vec![vec!["a", "b"], vec!["c", "d"], vec!["e"]]);
+    assert_eq!(collect(&plain, Direction::Backward, 2), vec![vec!["e", "d"], vec!["c", "b"], vec!["a"]]);
+    assert_eq!(collect(&plain, Direction::Forward, 5), vec![vec!["a", "b", "c", "d", "e"]]);
+
+    // changes between pages
+    let page = plain.page(None, 2, Direction::Forward).unwrap();
+    let token = page.next.unwrap();
+    plain.del(&"b").unwrap();
+    plain.set(&"a1", &"").unwrap();
+    plain.set(&"b1", &"").unwrap();
+    let page = plain.page(Some(&token), 2, Direction::Forward).unwrap();
+    let keys: Vec<String> = page.entries.iter().map(|cv| cv.get_key()).collect();
+    assert_eq!(keys, vec!["b1", "c"]);
+    let page = plain.page(Some(&token), 10, Direction::Backward).unwrap();
+    let keys: Vec<String> = page.entries.iter().map(|cv| cv.get_key()).collect();
+    assert_eq!(keys, vec!["a1", "a"]);
+    assert!(page.next.is_none());
+
+    let after = PageToken::after_key(&"c");
+    let keys: Vec<String> = plain.page(Some(&after), 10, Direction::Forward).unwrap()
+        .entries.iter().map(|cv| cv.get_key()).collect();
+    assert_eq!(keys, vec!["d", "e"]);
+    let after = PageToken::after_key(&"z");
+    assert!(plain.page(Some(&after), 10, Direction::Forward).unwrap().entries.is_empty());
+    assert_eq!(plain.page(Some(&after), 10, Direction::Backward).unwrap().entries.len(), 6);
+    assert!(matches!(plain.page(None, 0, Direction::Forward), Err(StateError(_))));
+    assert!(PageToken::from_bytes(b"garbage").is_none());
+
+    for (key, value) in [("a", "1"), ("a", "2"), ("a", "3"), ("b", "1"), ("c", "1"), ("c", "2")] {
+        dups.set(&key, &value).unwrap();
+    }
+    assert_eq!(collect(&dups, Direction::Forward, 2),
+               vec![vec!["a1", "a2"], vec!["a3", "b1"], vec!["c1", "c2"]]);
+    assert_eq!(collect(&dups, Direction::Backward, 4),
+               vec![vec!["c2", "c1", "b1", "a3"], vec!["a2", "a1"]]);
+
+    let page = dups.page(None, 2, Direction::Forward).unwrap();
+    let token = page.next.unwrap();
+    dups.del_item(&"a", &"2").unwrap();
+    dups.set(&"a", &"25").unwrap();
+    let page = dups.page(Some(&token), 2, Direction::Forward).unwrap();
+    assert_eq!(entries(&page), vec!["a25", "a3"]);
+    let page = dups.page(Some(&token), 2, Direction::Backward).unwrap();
+    assert_eq!(entries(&page), vec!["a1"]);
+
+    // the whole key of the token is gone
+    let page = dups.page(None, 4, Direction::Forward).unwrap();
+    let token = page.next.unwrap();
+    dups.del(&"b").unwrap();
+    let page = dups.page(Some(&token), 4, Direction::Forward).unwrap();
+    assert_eq!(entries(&page), vec!["c1", "c2"]);
+    let page = dups.page(Some(&token), 1, Direction::Backward).unwrap();
+    assert_eq!(entries(&page), vec!["a3"]);
+}
+
+#[test]
+fn test_split_ranges() {
+    let env = EnvBuilder::new()
+        .max_dbs(5)
+        .open(next_path(), USER_DIR)
+        .unwrap();
+    let plain = env.create_db("plain", DbFlags::empty()).unwrap();
+    let dups = env.create_db("dups", DbFlags::DbAllowDups).unwrap();
+    let txn = env.new_transaction().unwrap();
+    let plain = txn.bind(&plain);
+    let dups = txn.bind(&dups);
+
+    assert_eq!(plain.split_ranges(4).unwrap(), vec![KeyRange { start: None, end: None }]);
+    for i in 0..1000 {
+        plain.set(&format!("key{:04}", i), &"").unwrap();
+    }
+    let ranges = plain.split_ranges(4).unwrap();
+    assert_eq!(ranges.len(), 4);
+    assert_eq!(ranges[0].start, None);
+    assert_eq!(ranges[3].end, None);
+    for pair in ranges.windows(2) {
+        assert_eq!(pair[0].end, pair[1].start);
+    }
+    for range in &ranges {
+        assert_eq!(plain.count_range(range).unwrap(), 250);
+    }
+    assert_eq!(plain.split_ranges(1).unwrap().len(), 1);
+    assert_eq!(plain.split_ranges(5000).unwrap().len(), 1000);
+
+    // a key with most of the items can't be split
+    for i in 0..90 {
+        dups.set(&"big", &format!("{:02}", i)).unwrap();
+    }
+    for key in ["a", "c", "d", "e", "f", "g", "h", "i", "j", "k"] {
+        dups.set(&key, &"").unwrap();
+    }
+    let ranges = dups.split_ranges(4).unwrap();
+    let total: usize = ranges.iter().map(|r| dups.count_range(r).unwrap()).sum();
+    assert_eq!(total, 100);
+    assert!(ranges.len() <= 4);
+    assert!(ranges.iter().any(|r| dups.count_range(r).unwrap() >= 90));
+}
+
+#[test]
+fn test_split_ranges_by_seeks() {
+    let env = EnvBuilder::new()
+        .max_dbs(5)
+        .open(next_path(), USER_DIR)
+        .unwrap();
+    let plain = env.create_db("plain", DbFlags::empty()).unwrap();
+    let reversed = env.create_db("reversed", DbFlags::DbReverseKey).unwrap();
+    let txn = env.new_transaction().unwrap();
+    let plain = txn.bind(&plain);
+    let reversed = txn.bind(&reversed);
+    for i in 0..20000 {
+        let key = format!("key{:05}", i);
+        plain.set(&key, &"").unwrap();
+        reversed.set(&key, &"").unwrap();
+    }
+
+    let ranges = plain.split_ranges(4).unwrap();
+    assert_eq!(ranges, plain.split_ranges(4).unwrap());
+    assert_eq!(ranges.len(), 4);
+    assert_eq!(ranges[0].start, None);
+    assert_eq!(ranges[3].end, None);
+    let mut total = 0;
+    for pair in ranges.windows(2) {
+        assert_eq!(pair[0].end, pair[1].start);
+    }
+    for range in &ranges {
+        let count = plain.count_range(range).unwrap();
+        assert!((3000..7000).contains(&count), "uneven range of {} items", count);
+        total += count;
+    }
+    assert_eq!(total, 20000);
+
+    // seeks don't work out with reversed keys, they are walked
+    let ranges = reversed.split_ranges(4).unwrap();
+    assert_eq!(ranges.len(), 4);
+    for range in &ranges {
+        assert_eq!(reversed.count_range(range).unwrap(), 5000);
+    }
+}
+
+#[test]
+fn test_sample() {
+    let env = EnvBuilder::new()
+        .max_dbs(5)
+        .open(next_path(), USER_DIR)
+        .unwrap();
+    let plain = env.create_db("plain", DbFlags::empty()).unwrap();
+    let dups = env.create_db("dups", DbFlags::DbAllowDups).unwrap();
+    let reversed = env.create_db("reversed", DbFlags::DbReverseKey).unwrap();
+    let txn = env.new_transaction().unwrap();
+    let plain = txn.bind(&plain);
+    let dups = txn.bind(&dups);
+    let reversed = txn.bind(&reversed);
+
+    assert!(plain.sample::<String>(5, 1).unwrap().is_empty());
+    for i in 0..1000 {
+        let key = format!("key{:04}", i);
+        plain.set(&key, &"").unwrap();
+        reversed.set(&key, &"").unwrap();
+    }
+    assert!(plain.sample::<String>(0, 1).unwrap().is_empty());
+
+    for db in [&plain, &reversed] {
+        let sample: Vec<String> = db.sample(20, 42).unwrap();
+        assert_eq!(sample.len(), 20);
+        assert_eq!(sample, db.sample::<String>(20, 42).unwrap());
+        assert_ne!(sample, db.sample::<String>(20, 43).unwrap());
+        let distinct: HashSet<&String> = sample.iter().collect();
+        assert_eq!(distinct.len(), 20);
+        assert!(sample.iter().all(|k| db.get::<()>(k).is_ok()));
+    }
+
+    // asking for more keys than there are returns all of them
+    let all: Vec<String> = plain.sample(5000, 7).unwrap();
+    assert_eq!(all.len(), 1000);
+    assert_eq!(all.iter().collect::<HashSet<_>>().len(), 1000);
+
+    // keys with many items are returned once
+    for key in ["a", "b", "c"] {
+        for i in 0..10 {
+            dups.set(&key, &format!("{}", i)).unwrap();
+        }
+    }
+    let mut sample: Vec<String> = dups.sample(3, 3).unwrap();
+    sample.sort();
+    assert_eq!(sample, vec!["a", "b", "c"]);
+    let sample: Vec<String> = dups.sample(2, 3).unwrap();
+    assert_eq!(sample.len(), 2);
+    assert_ne!(sample[0], sample[1]);
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn test_par_iter() {
+    use rayon::iter::ParallelIterator;
+
+    let env = EnvBuilder::new()
+        .flags(EnvCreateFlags::EnvCreateNoTls)
+        .open(next_path(), USER_DIR)
+        .unwrap();
+    let db = env.get_default_db(DbFlags::empty()).unwrap();
+    let txn = env.new_transaction().unwrap();
+    for i in 0..1000u32 {
+        txn.bind(&db).set(&format!("key{:04}", i), &format!("{}", i)).unwrap();
+    }
+    txn.commit().unwrap();
+
+    let reader = env.get_reader().unwrap();
+    let mut items: Vec<(Vec<u8>, Vec<u8>)> = reader.bind(&db).par_iter(8).unwrap().collect();
+    items.sort();
+    assert_eq!(items.len(), 1000);
+    assert_eq!(items[999], (b"key0999".to_vec(), b"999".to_vec()));
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn test_par_iter_after_commit() {
+    let env = EnvBuilder::new()
+        .flags(EnvCreateFlags::EnvCreateNoTls)
+        .open(next_path(), USER_DIR)
+        .unwrap();
+    let db = env.get_default_db(DbFlags::empty()).unwrap();
+    let txn = env.new_transaction().unwrap();
+    for i in 0..100u32 {
+        txn.bind(&db).set(&format!("key{:03}", i), &"").unwrap();
+    }
+    txn.commit().unwrap();
+
+    let reader = env.get_reader().unwrap();
+    // workers won't get the snapshot of the reader
+    let txn = env.new_transaction().unwrap();
+    txn.bind(&db).set(&"key100", &"").unwrap();
+    txn.commit().unwrap();
+
+    assert!(matches!(reader.bind(&db).par_iter(4), Err(StateError(_))));
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn test_par_iter_under_writes() {
+    use rayon::iter::ParallelIterator;
+    use std::sync::Arc;
+    use std::sync::atomic::AtomicBool;
+
+    let env = EnvBuilder::new()
+        .flags(EnvCreateFlags::EnvCreateNoTls)
+        .open(next_path(), USER_DIR)
+        .unwrap();
+    let db = env.get_default_db(DbFlags::empty()).unwrap();
+    let done = Arc::new(AtomicBool::new(false));
+    let writer = {
+        let (env, done) = (env.clone(), done.clone());
+        thread::spawn(move || {
+            let mut i = 0u32;
+            while !done.load(Ordering::SeqCst) {
+                let txn = env.new_transaction().unwrap();
+                txn.bind(&db).set(&format!("key{:06}", i), &"").unwrap();
+                txn.commit().unwrap();
+                i += 1;
+            }
+        })
+    };
+
+    // every call sees a whole snapshot or fails before any item
+    for _ in 0..50 {
+        let reader = env.get_reader().unwrap();
+        let db = reader.bind(&db);
+        match db.par_iter(4) {
+            Ok(items) => assert_eq!(items.count(), db.len().unwrap()),
+            Err(StateError(_)) => (),
+            Err(e) => panic!("unexpected error {}", e),
+        }
+    }
+    done.store(true, Ordering::SeqCst);
+    writer.join().unwrap();
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn test_par_iter_needs_notls() {
+    let env = EnvBuilder::new().open(next_path(), USER_DIR).unwrap();
+    let db = env.get_default_db(DbFlags::empty()).unwrap();
+    let reader = env.get_reader().unwrap();
+    assert!(matches!(reader.bind(&db).par_iter(4), Err(StateError(_))));
+}
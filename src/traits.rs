@@ -1,19 +1,81 @@
 //! Conversion of data structures to and from MDB_val
 //!
-//! Since MDB_val is valid through whole transaction, it is kind of safe
-//! to keep plain data, i.e. to keep raw pointers and transmute them back
-//! and forward into corresponding data structures to avoid any unnecessary
-//! copying.
+//! Since MDB_val is valid through whole readonly transaction, it is kind
+//! of safe to keep plain data, i.e. to keep raw pointers and convert them
+//! back and forward into corresponding data structures to avoid any
+//! unnecessary copying.
 //!
-//! `MdbValue` is a simple wrapper with bounded lifetime which should help
-//! keep it sane, i.e. provide compile errors when data retrieved outlives
-//! transaction.
+//! Data is read through `FromTxnValue`, which carries the lifetime of
+//! the transaction, so borrowed data retrieved from a database can't
+//! outlive it:
 //!
-//! It would be extremely helpful to create `compile-fail` tests to ensure
-//! this, but unfortunately there is no way yet.
+//! ```compile_fail
+//! # use kn0sys_lmdb_rs::{EnvBuilder, DbFlags};
+//! # let dir = std::env::temp_dir().join("lmdb-rs-doc-outlive-get");
+//! # let env = EnvBuilder::new().open(&dir, 0o777).unwrap();
+//! # let db_handle = env.get_default_db(DbFlags::empty()).unwrap();
+//! let value: &str = {
+//!     let reader = env.get_reader().unwrap();
+//!     let db = reader.bind(&db_handle);
+//!     db.get(&"key").unwrap()
+//! };
+//! println!("{}", value);
+//! ```
+//!
+//! The same goes for data handed out by cursors:
+//!
+//! ```compile_fail
+//! # use kn0sys_lmdb_rs::{EnvBuilder, DbFlags};
+//! # let dir = std::env::temp_dir().join("lmdb-rs-doc-outlive-iter");
+//! # let env = EnvBuilder::new().open(&dir, 0o777).unwrap();
+//! # let db_handle = env.get_default_db(DbFlags::empty()).unwrap();
+//! let keys: Vec<&[u8]> = {
+//!     let reader = env.get_reader().unwrap();
+//!     let db = reader.bind(&db_handle);
+//!     db.iter().unwrap().map(|cv| cv.get_key()).collect()
+//! };
+//! println!("{:?}", keys);
+//! ```
+//!
+//! Write transactions reuse pages of changed or deleted data, so they
+//! hand out only owned data and nothing borrowed is left dangling after
+//! a write:
+//!
+//! ```compile_fail
+//! # use kn0sys_lmdb_rs::{EnvBuilder, DbFlags};
+//! # let dir = std::env::temp_dir().join("lmdb-rs-doc-write-borrow");
+//! # let env = EnvBuilder::new().open(&dir, 0o777).unwrap();
+//! # let db_handle = env.get_default_db(DbFlags::empty()).unwrap();
+//! let txn = env.new_transaction().unwrap();
+//! let db = txn.bind(&db_handle);
+//! db.set(&"key", &"value").unwrap();
+//! let value: &str = db.get(&"key").unwrap();
+//! db.del(&"key").unwrap();
+//! println!("{}", value);
+//! ```
+//!
+//! Owned types such as `String` and `Vec<u8>` copy the data out and may
+//! live as long as needed:
+//!
+//! ```
+//! # use kn0sys_lmdb_rs::{EnvBuilder, DbFlags};
+//! # let dir = std::env::temp_dir().join("lmdb-rs-doc-owned");
+//! # let env = EnvBuilder::new().open(&dir, 0o777).unwrap();
+//! # let db_handle = env.get_default_db(DbFlags::empty()).unwrap();
+//! # {
+//! #     let txn = env.new_transaction().unwrap();
+//! #     txn.bind(&db_handle).set(&"key", &"value").unwrap();
+//! #     txn.commit().unwrap();
+//! # }
+//! let value: String = {
+//!     let reader = env.get_reader().unwrap();
+//!     let db = reader.bind(&db_handle);
+//!     db.get(&"key").unwrap()
+//! };
+//! assert_eq!(value, "value");
+//! ```
 
 
-use std::{self, mem, slice};
 
 use crate::MdbValue;
 use ffi::MDB_val;
@@ -27,26 +89,96 @@ pub trait ToMdbValue {
 }
 
 /// `FromMdbValue` is supposed to reconstruct a value from
-/// memory slice. It allows to use zero copy where it is
-/// required.
+/// memory slice. `MdbValue` may hold its bytes itself, see
+/// `ToMdbValue for &u64`, so data borrowed from it lives only
+/// as long as the value; `&str` and `&[u8]` borrowing from the
+/// transaction are read through `FromTxnValue` instead.
+pub trait FromMdbValue<'a> {
+    fn from_mdb_value(value: &MdbValue<'a>) -> Self;
+}
+
+mod sealed {
+    pub trait Sealed {}
+
+    impl Sealed for super::ReadOnly {}
+    impl Sealed for super::ReadWrite {}
+}
+
+/// Mode of the transaction data is read in, see `FromTxnValue`
+pub trait TxnMode: sealed::Sealed {
+    /// Whether data kept around to be decoded later has to be copied
+    /// out, as the transaction may rewrite its pages in the meantime
+    #[doc(hidden)]
+    const COPIES_DATA: bool;
+}
+
+/// Mode of `ReadonlyTransaction`
+#[derive(Debug)]
+pub enum ReadOnly {}
 
-pub trait FromMdbValue {
-    fn from_mdb_value(value: &MdbValue) -> Self;
+/// Mode of `Transaction`
+#[derive(Debug)]
+pub enum ReadWrite {}
+
+impl TxnMode for ReadOnly {
+    const COPIES_DATA: bool = false;
+}
+
+impl TxnMode for ReadWrite {
+    const COPIES_DATA: bool = true;
+}
+
+/// `FromTxnValue` is what can be read in a transaction of mode `M`
+/// from `data` valid for `'a`. Readonly transactions may hand out any
+/// `FromMdbValue` as well as `&str` and `&[u8]` borrowing from the
+/// transaction. Write transactions reuse pages once data is changed
+/// or deleted, so they hand out only owned data, i.e. types which are
+/// `FromMdbValue` for any lifetime.
+pub trait FromTxnValue<'a, M: TxnMode>: Sized {
+    fn from_txn_value(data: &'a [u8]) -> Self;
+}
+
+/// `MdbValue` over data which lives for `'a`
+fn borrowed_value(data: &[u8]) -> MdbValue<'_> {
+    unsafe { MdbValue::new(data.as_ptr() as *const libc::c_void, data.len()) }
+}
+
+impl<'a, T: FromMdbValue<'a>> FromTxnValue<'a, ReadOnly> for T {
+    fn from_txn_value(data: &'a [u8]) -> T {
+        T::from_mdb_value(&borrowed_value(data))
+    }
+}
+
+impl<'a, T: for<'b> FromMdbValue<'b>> FromTxnValue<'a, ReadWrite> for T {
+    fn from_txn_value(data: &'a [u8]) -> T {
+        T::from_mdb_value(&borrowed_value(data))
+    }
+}
+
+impl<'a> FromTxnValue<'a, ReadOnly> for &'a str {
+    fn from_txn_value(data: &'a [u8]) -> &'a str {
+        std::str::from_utf8(data).unwrap()
+    }
+}
+
+impl<'a> FromTxnValue<'a, ReadOnly> for &'a [u8] {
+    fn from_txn_value(data: &'a [u8]) -> &'a [u8] {
+        data
+    }
 }
 
 impl ToMdbValue for Vec<u8> {
     fn to_mdb_value(&self) -> MdbValue<'_> {
         unsafe {
-            MdbValue::new(std::mem::transmute::<*const u8, *const libc::c_void>(self.as_ptr()), self.len())
+            MdbValue::new(self.as_ptr() as *const libc::c_void, self.len())
         }
     }
 }
 
 impl ToMdbValue for String {
-    fn to_mdb_value<'a>(&'a self) -> MdbValue<'a> {
+    fn to_mdb_value(&self) -> MdbValue<'_> {
         unsafe {
-            let t: &'a str = self;
-            MdbValue::new(std::mem::transmute::<*const u8, *const libc::c_void>(t.as_ptr()), t.len())
+            MdbValue::new(self.as_ptr() as *const libc::c_void, self.len())
         }
     }
 }
@@ -54,7 +186,7 @@ impl ToMdbValue for String {
 impl<'a> ToMdbValue for &'a str {
     fn to_mdb_value(&self) -> MdbValue<'_> {
         unsafe {
-            MdbValue::new(mem::transmute::<*const u8, *const libc::c_void>(self.as_ptr()), self.len())
+            MdbValue::new(self.as_ptr() as *const libc::c_void, self.len())
         }
     }
 }
@@ -62,27 +194,22 @@ impl<'a> ToMdbValue for &'a str {
 impl<'a> ToMdbValue for &'a [u8] {
     fn to_mdb_value(&self) -> MdbValue<'_> {
         unsafe {
-            MdbValue::new(std::mem::transmute::<*const u8, *const libc::c_void>(self.as_ptr()),
-                          self.len())
+            MdbValue::new(self.as_ptr() as *const libc::c_void, self.len())
         }
     }
 }
 
+/// Big endian, so that keys sort numerically
 impl<'a> ToMdbValue for &'a u64 {
     fn to_mdb_value(&self) -> MdbValue<'_> {
-        unsafe {
-            let l = self.to_be_bytes();
-            MdbValue::new(std::mem::transmute::<[u8; 8], *const libc::c_void>(l), l.len())
-        }
+        MdbValue::new_inline(self.to_be_bytes())
     }
 }
 
+/// Big endian, keys sort numerically among non-negative ones
 impl<'a> ToMdbValue for &'a i64 {
     fn to_mdb_value(&self) -> MdbValue<'_> {
-        unsafe {
-            let l = self.to_be_bytes();
-            MdbValue::new(std::mem::transmute::<[u8; 8], *const libc::c_void>(l), l.len())
-        }
+        MdbValue::new_inline(self.to_be_bytes())
     }
 }
 
@@ -102,43 +229,20 @@ impl<'a> ToMdbValue for MdbValue<'a> {
 }
 
 
-impl FromMdbValue for String {
-    fn from_mdb_value(value: &MdbValue) -> String {
-        unsafe {
-            let ptr = mem::transmute::<*const libc::c_void, *const u8>(value.get_ref());
-            let data: Vec<u8> = slice::from_raw_parts(ptr, value.get_size()).to_vec();
-            String::from_utf8(data).unwrap()
-        }
+impl<'a> FromMdbValue<'a> for String {
+    fn from_mdb_value(value: &MdbValue<'a>) -> String {
+        String::from_utf8(value.as_bytes().to_vec()).unwrap()
     }
 }
 
-impl FromMdbValue for Vec<u8> {
-    fn from_mdb_value(value: &MdbValue) -> Vec<u8> {
-        unsafe {
-            let ptr = mem::transmute::<*const libc::c_void, *const u8>(value.get_ref());
-            slice::from_raw_parts(ptr, value.get_size()).to_vec()
-        }
-    }
-}
-
-impl FromMdbValue for () {
-    fn from_mdb_value(_: &MdbValue) {
-    }
-}
-
-impl<'b> FromMdbValue for &'b str {
-    fn from_mdb_value(value: &MdbValue) -> &'b str {
-        unsafe {
-            std::mem::transmute(slice::from_raw_parts(value.get_ref(), value.get_size()))
-        }
+impl<'a> FromMdbValue<'a> for Vec<u8> {
+    fn from_mdb_value(value: &MdbValue<'a>) -> Vec<u8> {
+        value.as_bytes().to_vec()
     }
 }
 
-impl<'b> FromMdbValue for &'b [u8] {
-    fn from_mdb_value(value: &MdbValue) -> &'b [u8] {
-        unsafe {
-            std::mem::transmute(slice::from_raw_parts(value.get_ref(), value.get_size()))
-        }
+impl<'a> FromMdbValue<'a> for () {
+    fn from_mdb_value(_: &MdbValue<'a>) {
     }
 }
 
@@ -1,5 +1,6 @@
 use libc::c_int;
-use std::ffi::CStr;
+use std::ffi::{CStr, CString};
+use std::path::Path;
 
 use ffi::mdb_strerror;
 
@@ -8,3 +9,18 @@ pub fn error_msg(code: c_int) -> String {
         String::from_utf8(CStr::from_ptr(mdb_strerror(code)).to_bytes().to_vec()).unwrap()
     }
 }
+
+/// Converts path to C string as is, i.e. without requiring it
+/// to be a valid UTF-8 on Unix. Returns `None` for paths with
+/// interior nul bytes.
+#[cfg(unix)]
+pub fn path_to_cstring(path: &Path) -> Option<CString> {
+    use std::os::unix::ffi::OsStrExt;
+
+    CString::new(path.as_os_str().as_bytes()).ok()
+}
+
+#[cfg(not(unix))]
+pub fn path_to_cstring(path: &Path) -> Option<CString> {
+    CString::new(path.to_str()?).ok()
+}